@@ -0,0 +1,119 @@
+// compares the plain interpreter against the decode cache and translated
+// code; run with `cargo bench`
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use advent2019::intcode::aot::{Compiled, Native};
+use advent2019::intcode::asm::assemble;
+use advent2019::intcode::decode::DecodeCache;
+use advent2019::intcode::{self, VM};
+
+// generated by intcode::aot::rust_source
+mod native {
+    include!("native/day02.rs");
+    include!("native/countdown.rs");
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Engine {
+    Plain,
+    Predecoded,
+    Shared,
+    Compiled,
+    Translated,
+}
+
+const ENGINES: [(Engine, &str); 5] = [
+    (Engine::Plain, "plain"),
+    (Engine::Predecoded, "predecoded"),
+    (Engine::Shared, "shared cache"),
+    (Engine::Compiled, "closures"),
+    (Engine::Translated, "rust source"),
+];
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    // best of a few rounds, after one to warm up
+    f();
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+// every noun and verb of day 2, as in a brute force search; each run is too
+// short to pay for a cache of its own, so predecoding is the same as sharing
+fn day02b(program: &[isize], engine: Engine) -> isize {
+    let cache = DecodeCache::new(program);
+    let compiled = Compiled::new(program);
+    let mut program = program.to_vec();
+    let mut sum = 0;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            program[1] = noun;
+            program[2] = verb;
+            let mut vm = VM::new(&program);
+            match engine {
+                Engine::Plain => vm.run(),
+                Engine::Predecoded | Engine::Shared => {
+                    vm.share_decoded(&cache);
+                    vm.run()
+                },
+                Engine::Compiled => compiled.run(&mut vm),
+                Engine::Translated => native::day02.run(&mut vm),
+            }.unwrap();
+            sum += black_box(vm.mem[0]);
+        }
+    }
+    sum
+}
+
+// one long running VM, where decoding dominates; a shared cache is the same
+// as predecoding here
+fn countdown(program: &[isize], engine: Engine) -> isize {
+    let mut vm = VM::new(program);
+    vm.push_input(1_000_000);
+    match engine {
+        Engine::Plain => vm.run(),
+        Engine::Predecoded | Engine::Shared => {
+            vm.predecode();
+            vm.run()
+        },
+        Engine::Compiled => Compiled::new(program).run(&mut vm),
+        Engine::Translated => native::countdown.run(&mut vm),
+    }.unwrap();
+    black_box(vm.mem[19])
+}
+
+fn main() {
+    let day02 = intcode::parse(&advent2019::load("02.txt")).unwrap();
+    // benches/native/countdown.rs is translated from this
+    let spin = assemble("
+                read [n]
+        loop:   add [n], -1, [n]
+                mul [n], 3, [t]
+                less [t], 10, [t]
+                jnz [n], loop
+                halt
+        n:      data 0
+        t:      data 0
+    ").unwrap();
+
+    let expected = day02b(&day02, Engine::Plain);
+    for (engine, name) in ENGINES.iter().filter(|(e, _)| *e != Engine::Predecoded) {
+        assert_eq!(day02b(&day02, *engine), expected, "{} differs", name);
+        let t = time(|| {
+            day02b(&day02, *engine);
+        });
+        println!("day02b     {:<14} {:>10.2?}", name, t);
+    }
+    for (engine, name) in ENGINES.iter().filter(|(e, _)| *e != Engine::Shared) {
+        let t = time(|| {
+            countdown(&spin, *engine);
+        });
+        println!("countdown  {:<14} {:>10.2?}", name, t);
+    }
+}
@@ -0,0 +1,45 @@
+// translated from a 20 word intcode program by intcode::aot::rust_source;
+// anything else is left to the interpreter
+#[allow(clippy::all, unreachable_code, unused_imports, unused_variables)]
+pub fn countdown(vm: &mut advent2019::intcode::VM) -> Option<isize> {
+    use advent2019::intcode::aot::{addr, rel, unchanged};
+    const B2: [isize; 15] = [1001, 18, -1, 18, 1002, 18, 3, 19, 1007, 19, 10, 19, 1005, 18, 2];
+    loop {
+        let from = vm.pc;
+        match from {
+            2 | 6 | 10 | 14 if unchanged(vm, from, 2, &B2) => {
+                if from <= 2 {
+                    // add [18], -1, [18]
+                    let x = vm.mem.get(18);
+                    let y = -1;
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 2; return None; } };
+                    vm.mem[18] = v;
+                }
+                if from <= 6 {
+                    // mul [18], 3, [19]
+                    let x = vm.mem.get(18);
+                    let y = 3;
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 6; return None; } };
+                    vm.mem[19] = v;
+                }
+                if from <= 10 {
+                    // less [19], 10, [19]
+                    let x = vm.mem.get(19);
+                    let y = 10;
+                    let v = (x < y) as isize;
+                    vm.mem[19] = v;
+                }
+                if from <= 14 {
+                    // jnz [18], 2
+                    let c = vm.mem.get(18);
+                    if c != 0 {
+                        vm.pc = 2;
+                        continue;
+                    }
+                    vm.pc = 17;
+                }
+            },
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// translated from a 117 word intcode program by intcode::aot::rust_source;
+// anything else is left to the interpreter
+#[allow(clippy::all, unreachable_code, unused_imports, unused_variables)]
+pub fn day02(vm: &mut advent2019::intcode::VM) -> Option<isize> {
+    use advent2019::intcode::aot::{addr, rel, unchanged};
+    const B0: [isize; 113] = [1, 0, 0, 3, 1, 1, 2, 3, 1, 3, 4, 3, 1, 5, 0, 3, 2, 6, 1, 19, 1, 19, 5, 23, 2, 10, 23, 27, 2, 27, 13, 31, 1, 10, 31, 35, 1, 35, 9, 39, 2, 39, 13, 43, 1, 43, 5, 47, 1, 47, 6, 51, 2, 6, 51, 55, 1, 5, 55, 59, 2, 9, 59, 63, 2, 6, 63, 67, 1, 13, 67, 71, 1, 9, 71, 75, 2, 13, 75, 79, 1, 79, 10, 83, 2, 83, 9, 87, 1, 5, 87, 91, 2, 91, 6, 95, 2, 13, 95, 99, 1, 99, 5, 103, 1, 103, 2, 107, 1, 107, 10, 0, 99];
+    loop {
+        let from = vm.pc;
+        match from {
+            0 | 4 | 8 | 12 | 16 | 20 | 24 | 28 | 32 | 36 | 40 | 44 | 48 | 52 | 56 | 60 | 64 | 68 | 72 | 76 | 80 | 84 | 88 | 92 | 96 | 100 | 104 | 108 if unchanged(vm, from, 0, &B0) => {
+                if from <= 0 {
+                    // add [0], [0], [3]
+                    let x = vm.mem.get(0);
+                    let y = vm.mem.get(0);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 0; return None; } };
+                    vm.mem[3] = v;
+                }
+                if from <= 4 {
+                    // add [1], [2], [3]
+                    let x = vm.mem.get(1);
+                    let y = vm.mem.get(2);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 4; return None; } };
+                    vm.mem[3] = v;
+                }
+                if from <= 8 {
+                    // add [3], [4], [3]
+                    let x = vm.mem.get(3);
+                    let y = vm.mem.get(4);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 8; return None; } };
+                    vm.mem[3] = v;
+                }
+                if from <= 12 {
+                    // add [5], [0], [3]
+                    let x = vm.mem.get(5);
+                    let y = vm.mem.get(0);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 12; return None; } };
+                    vm.mem[3] = v;
+                }
+                if from <= 16 {
+                    // mul [6], [1], [19]
+                    let x = vm.mem.get(6);
+                    let y = vm.mem.get(1);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 16; return None; } };
+                    vm.mem[19] = v;
+                }
+                if from <= 20 {
+                    // add [19], [5], [23]
+                    let x = vm.mem.get(19);
+                    let y = vm.mem.get(5);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 20; return None; } };
+                    vm.mem[23] = v;
+                }
+                if from <= 24 {
+                    // mul [10], [23], [27]
+                    let x = vm.mem.get(10);
+                    let y = vm.mem.get(23);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 24; return None; } };
+                    vm.mem[27] = v;
+                }
+                if from <= 28 {
+                    // mul [27], [13], [31]
+                    let x = vm.mem.get(27);
+                    let y = vm.mem.get(13);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 28; return None; } };
+                    vm.mem[31] = v;
+                }
+                if from <= 32 {
+                    // add [10], [31], [35]
+                    let x = vm.mem.get(10);
+                    let y = vm.mem.get(31);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 32; return None; } };
+                    vm.mem[35] = v;
+                }
+                if from <= 36 {
+                    // add [35], [9], [39]
+                    let x = vm.mem.get(35);
+                    let y = vm.mem.get(9);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 36; return None; } };
+                    vm.mem[39] = v;
+                }
+                if from <= 40 {
+                    // mul [39], [13], [43]
+                    let x = vm.mem.get(39);
+                    let y = vm.mem.get(13);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 40; return None; } };
+                    vm.mem[43] = v;
+                }
+                if from <= 44 {
+                    // add [43], [5], [47]
+                    let x = vm.mem.get(43);
+                    let y = vm.mem.get(5);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 44; return None; } };
+                    vm.mem[47] = v;
+                }
+                if from <= 48 {
+                    // add [47], [6], [51]
+                    let x = vm.mem.get(47);
+                    let y = vm.mem.get(6);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 48; return None; } };
+                    vm.mem[51] = v;
+                }
+                if from <= 52 {
+                    // mul [6], [51], [55]
+                    let x = vm.mem.get(6);
+                    let y = vm.mem.get(51);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 52; return None; } };
+                    vm.mem[55] = v;
+                }
+                if from <= 56 {
+                    // add [5], [55], [59]
+                    let x = vm.mem.get(5);
+                    let y = vm.mem.get(55);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 56; return None; } };
+                    vm.mem[59] = v;
+                }
+                if from <= 60 {
+                    // mul [9], [59], [63]
+                    let x = vm.mem.get(9);
+                    let y = vm.mem.get(59);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 60; return None; } };
+                    vm.mem[63] = v;
+                }
+                if from <= 64 {
+                    // mul [6], [63], [67]
+                    let x = vm.mem.get(6);
+                    let y = vm.mem.get(63);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 64; return None; } };
+                    vm.mem[67] = v;
+                }
+                if from <= 68 {
+                    // add [13], [67], [71]
+                    let x = vm.mem.get(13);
+                    let y = vm.mem.get(67);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 68; return None; } };
+                    vm.mem[71] = v;
+                }
+                if from <= 72 {
+                    // add [9], [71], [75]
+                    let x = vm.mem.get(9);
+                    let y = vm.mem.get(71);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 72; return None; } };
+                    vm.mem[75] = v;
+                }
+                if from <= 76 {
+                    // mul [13], [75], [79]
+                    let x = vm.mem.get(13);
+                    let y = vm.mem.get(75);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 76; return None; } };
+                    vm.mem[79] = v;
+                }
+                if from <= 80 {
+                    // add [79], [10], [83]
+                    let x = vm.mem.get(79);
+                    let y = vm.mem.get(10);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 80; return None; } };
+                    vm.mem[83] = v;
+                }
+                if from <= 84 {
+                    // mul [83], [9], [87]
+                    let x = vm.mem.get(83);
+                    let y = vm.mem.get(9);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 84; return None; } };
+                    vm.mem[87] = v;
+                }
+                if from <= 88 {
+                    // add [5], [87], [91]
+                    let x = vm.mem.get(5);
+                    let y = vm.mem.get(87);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 88; return None; } };
+                    vm.mem[91] = v;
+                }
+                if from <= 92 {
+                    // mul [91], [6], [95]
+                    let x = vm.mem.get(91);
+                    let y = vm.mem.get(6);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 92; return None; } };
+                    vm.mem[95] = v;
+                }
+                if from <= 96 {
+                    // mul [13], [95], [99]
+                    let x = vm.mem.get(13);
+                    let y = vm.mem.get(95);
+                    let v = match x.checked_mul(y) { Some(v) => v, None => { vm.pc = 96; return None; } };
+                    vm.mem[99] = v;
+                }
+                if from <= 100 {
+                    // add [99], [5], [103]
+                    let x = vm.mem.get(99);
+                    let y = vm.mem.get(5);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 100; return None; } };
+                    vm.mem[103] = v;
+                }
+                if from <= 104 {
+                    // add [103], [2], [107]
+                    let x = vm.mem.get(103);
+                    let y = vm.mem.get(2);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 104; return None; } };
+                    vm.mem[107] = v;
+                }
+                if from <= 108 {
+                    // add [107], [10], [0]
+                    let x = vm.mem.get(107);
+                    let y = vm.mem.get(10);
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 108; return None; } };
+                    vm.mem[0] = v;
+                }
+                if from <= 112 {
+                    // halt
+                    vm.pc = 112;
+                    return None;
+                }
+            },
+            _ => return None,
+        }
+    }
+}
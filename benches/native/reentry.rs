@@ -0,0 +1,44 @@
+// translated from a 18 word intcode program by intcode::aot::rust_source;
+// anything else is left to the interpreter
+#[allow(clippy::all, unreachable_code, unused_imports, unused_variables)]
+pub fn reentry(vm: &mut advent2019::intcode::VM) -> Option<isize> {
+    use advent2019::intcode::aot::{addr, rel, unchanged};
+    const B2: [isize; 13] = [1001, 16, -1, 16, 4, 16, 1001, 17, 1, 17, 1005, 16, 2];
+    loop {
+        let from = vm.pc;
+        match from {
+            2 | 6 | 8 | 12 if unchanged(vm, from, 2, &B2) => {
+                if from <= 2 {
+                    // add [16], -1, [16]
+                    let x = vm.mem.get(16);
+                    let y = -1;
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 2; return None; } };
+                    vm.mem[16] = v;
+                }
+                if from <= 6 {
+                    // write [16]
+                    let x = vm.mem.get(16);
+                    vm.pc = 8;
+                    return Some(x);
+                }
+                if from <= 8 {
+                    // add [17], 1, [17]
+                    let x = vm.mem.get(17);
+                    let y = 1;
+                    let v = match x.checked_add(y) { Some(v) => v, None => { vm.pc = 8; return None; } };
+                    vm.mem[17] = v;
+                }
+                if from <= 12 {
+                    // jnz [16], 2
+                    let c = vm.mem.get(16);
+                    if c != 0 {
+                        vm.pc = 2;
+                        continue;
+                    }
+                    vm.pc = 15;
+                }
+            },
+            _ => return None,
+        }
+    }
+}
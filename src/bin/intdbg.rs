@@ -0,0 +1,31 @@
+use std::io::{self, BufRead, Write};
+
+use advent2019::intcode::debugger::Debugger;
+use advent2019::intcode::{self, VM};
+
+fn main() {
+    let file = match std::env::args().nth(1) {
+        Some(file) => file,
+        None => {
+            eprintln!("usage: intdbg <file in ./input>");
+            std::process::exit(1);
+        },
+    };
+    let program = intcode::parse(&advent2019::load(&file)).expect("invalid program");
+    let mut dbg = Debugger::new(VM::new(&program));
+    print!("{}", dbg.list(0, 1));
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match dbg.command(&line) {
+            Some(text) => print!("{}", text),
+            None => break,
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use std::path::Path;
+
+use advent2019::intcode::fuzz;
+
+fn main() {
+    let mut args = std::env::args().skip(1).map(|a| a.parse::<u64>());
+    let (seed, cases) = match (args.next(), args.next()) {
+        (Some(Ok(seed)), Some(Ok(cases))) => (seed, cases as usize),
+        _ => {
+            eprintln!("usage: intfuzz <seed> <cases>");
+            std::process::exit(1);
+        },
+    };
+    let failures = fuzz::fuzz(seed, cases, 10_000);
+    for (case, mismatch) in &failures {
+        match case.save(Path::new("./input/fuzz")) {
+            Ok(path) => println!("{}: {}", path.display(), mismatch),
+            Err(e) => eprintln!("io error: {}", e),
+        }
+    }
+    println!("{} of {} cases failed", failures.len(), cases);
+}
@@ -0,0 +1,22 @@
+use std::io;
+
+use advent2019::intcode::ascii::Ascii;
+use advent2019::intcode::{self, VM};
+
+fn main() {
+    let file = match std::env::args().nth(1) {
+        Some(file) => file,
+        None => {
+            eprintln!("usage: intplay <file in ./input>");
+            std::process::exit(1);
+        },
+    };
+    let program = intcode::parse(&advent2019::load(&file)).expect("invalid program");
+    let mut term = Ascii::new(VM::new(&program));
+    let stdin = io::stdin();
+    match term.interact(stdin.lock(), io::stdout()) {
+        Ok(Ok(_)) => (),
+        Ok(Err(e)) => eprintln!("{}", e),
+        Err(e) => eprintln!("io error: {}", e),
+    }
+}
@@ -1,26 +1,67 @@
+use std::collections::VecDeque;
 use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::error::Error;
+use std::fmt;
+use std::num::ParseIntError;
 use std::sync::mpsc::{SyncSender, Receiver};
 use std::sync::mpsc;
 
-#[derive(Copy, Clone, Debug)]
+mod memory;
+pub mod ascii;
+pub mod asm;
+pub mod aot;
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod cfg;
+pub mod debugger;
+pub mod decode;
+pub mod decompile;
+pub mod disasm;
+pub mod ext;
+pub mod fuzz;
+pub mod limits;
+pub mod nic;
+pub mod pipeline;
+pub mod profile;
+pub mod snapshot;
+pub mod stream;
+pub mod symbolic;
+#[cfg(test)]
+mod testing;
+pub mod trace;
+pub mod watch;
+pub mod word;
+
+use decode::DecodeCache;
+use ext::{Extensions, Param, Spec};
+use limits::LoopCheck;
+pub use memory::Memory;
+use profile::Profile;
+use trace::Trace;
+use watch::{Access, Hit, Watches};
+pub use word::{Overflow, Word};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Mode {
     Ptr,
     Imm,
+    Rel,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Op {
     // 01 : a, b, dst
     // *dst := *a + *b
-    Add(Mode, Mode),
+    Add(Mode, Mode, Mode),
 
     // 02 : a, b, dst
     // *dst := *a * *b
-    Mul(Mode, Mode),
+    Mul(Mode, Mode, Mode),
 
     // 03 : dst
     // *dst := input
-    Read,
+    Read(Mode),
 
     // 04 : src
     // output := *src
@@ -34,198 +75,527 @@ pub enum Op {
 
     // 07 : a b dst
     // if (*a < *b) then *dst = 1 else *dst = 0
-    Less(Mode, Mode),
+    Less(Mode, Mode, Mode),
 
     // 08 : a b dst
     // if (*a == *b) then *dst = 1 else *dst = 0
-    Equal(Mode, Mode),
+    Equal(Mode, Mode, Mode),
+
+    // 09 : a
+    // base := base + *a
+    Rebase(Mode),
 
     // 99
     Halt,
+
+    // an op code registered with VM::register, with the modes of its
+    // parameters; only the first spec.params.len() are used
+    Ext(Spec, [Mode; 3]),
+}
+
+impl Mode {
+    pub fn from(digit: isize) -> Option<Self> {
+        match digit {
+            0 => Some(Self::Ptr),
+            1 => Some(Self::Imm),
+            2 => Some(Self::Rel),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> isize {
+        match self {
+            Self::Ptr => 0,
+            Self::Imm => 1,
+            Self::Rel => 2,
+        }
+    }
 }
 
 impl Op {
-    pub fn from(x: isize) -> Option<Self> {
-        let op = match x {
-               1 => Self::Add(Mode::Ptr, Mode::Ptr),
-             101 => Self::Add(Mode::Imm, Mode::Ptr),
-            1001 => Self::Add(Mode::Ptr, Mode::Imm),
-            1101 => Self::Add(Mode::Imm, Mode::Imm),
-
-               2 => Self::Mul(Mode::Ptr, Mode::Ptr),
-             102 => Self::Mul(Mode::Imm, Mode::Ptr),
-            1002 => Self::Mul(Mode::Ptr, Mode::Imm),
-            1102 => Self::Mul(Mode::Imm, Mode::Imm),
-
-            3 => Self::Read,
-
-              4 => Self::Write(Mode::Ptr),
-            104 => Self::Write(Mode::Imm),
-
-               5 => Self::Jump(true, Mode::Ptr, Mode::Ptr),
-             105 => Self::Jump(true, Mode::Imm, Mode::Ptr),
-            1005 => Self::Jump(true, Mode::Ptr, Mode::Imm),
-            1105 => Self::Jump(true, Mode::Imm, Mode::Imm),
-
-               6 => Self::Jump(false, Mode::Ptr, Mode::Ptr),
-             106 => Self::Jump(false, Mode::Imm, Mode::Ptr),
-            1006 => Self::Jump(false, Mode::Ptr, Mode::Imm),
-            1106 => Self::Jump(false, Mode::Imm, Mode::Imm),
-
-               7 => Self::Less(Mode::Ptr, Mode::Ptr),
-             107 => Self::Less(Mode::Imm, Mode::Ptr),
-            1007 => Self::Less(Mode::Ptr, Mode::Imm),
-            1107 => Self::Less(Mode::Imm, Mode::Imm),
-
-               8 => Self::Equal(Mode::Ptr, Mode::Ptr),
-             108 => Self::Equal(Mode::Imm, Mode::Ptr),
-            1008 => Self::Equal(Mode::Ptr, Mode::Imm),
-            1108 => Self::Equal(Mode::Imm, Mode::Imm),
+    // number of parameters taken by each opcode
+    pub fn arity(code: isize) -> Option<usize> {
+        match code {
+            1 | 2 | 7 | 8 => Some(3),
+            5 | 6 => Some(2),
+            3 | 4 | 9 => Some(1),
+            99 => Some(0),
+            _ => None,
+        }
+    }
 
+    // builds an instruction from its opcode and exactly one mode per parameter
+    pub fn new(code: isize, modes: &[Mode]) -> Option<Self> {
+        if Self::arity(code)? != modes.len() {
+            return None;
+        }
+        let m = |i: usize| modes[i];
+        let op = match code {
+            1 => Self::Add(m(0), m(1), m(2)),
+            2 => Self::Mul(m(0), m(1), m(2)),
+            3 => Self::Read(m(0)),
+            4 => Self::Write(m(0)),
+            5 => Self::Jump(true, m(0), m(1)),
+            6 => Self::Jump(false, m(0), m(1)),
+            7 => Self::Less(m(0), m(1), m(2)),
+            8 => Self::Equal(m(0), m(1), m(2)),
+            9 => Self::Rebase(m(0)),
             99 => Self::Halt,
             _ => return None,
         };
         Some(op)
     }
+
+    // the two low digits are the opcode and each digit above them is the
+    // mode of one parameter; mode digits past the last parameter must be zero
+    pub fn from(x: isize) -> Option<Self> {
+        if x < 0 {
+            return None;
+        }
+        let code = x % 100;
+        let arity = Self::arity(code)?;
+        let mut digits = x / 100;
+        let mut modes = [Mode::Ptr; 3];
+        for m in modes.iter_mut().take(arity) {
+            *m = Mode::from(digits % 10)?;
+            digits /= 10;
+        }
+        if digits != 0 {
+            return None;
+        }
+        Self::new(code, &modes[..arity])
+    }
+
+    pub fn code(&self) -> isize {
+        match self {
+            Self::Add(_, _, _) => 1,
+            Self::Mul(_, _, _) => 2,
+            Self::Read(_) => 3,
+            Self::Write(_) => 4,
+            Self::Jump(true, _, _) => 5,
+            Self::Jump(false, _, _) => 6,
+            Self::Less(_, _, _) => 7,
+            Self::Equal(_, _, _) => 8,
+            Self::Rebase(_) => 9,
+            Self::Halt => 99,
+            Self::Ext(spec, _) => spec.code,
+        }
+    }
+
+    pub fn encode(&self) -> isize {
+        self.modes().into_iter()
+            .rev()
+            .fold(0, |acc, m| acc * 10 + m.digit()) * 100 + self.code()
+    }
+
+    // mode of the parameter written to, if any
+    pub fn dst(&self) -> Option<Mode> {
+        match *self {
+            Self::Add(_, _, m) | Self::Mul(_, _, m) => Some(m),
+            Self::Less(_, _, m) | Self::Equal(_, _, m) => Some(m),
+            Self::Read(m) => Some(m),
+            Self::Ext(spec, m) if spec.params.last() == Some(&Param::Out) => Some(m[spec.params.len() - 1]),
+            _ => None,
+        }
+    }
+
+    // well formed but unexecutable: a destination can't be immediate
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Ext(spec, m) => spec.params.iter().zip(m).all(|(p, m)| *p == Param::In || *m != Mode::Imm),
+            _ => self.dst() != Some(Mode::Imm),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Add(_, _, _) => "add",
+            Self::Mul(_, _, _) => "mul",
+            Self::Read(_) => "read",
+            Self::Write(_) => "write",
+            Self::Jump(true, _, _) => "jnz",
+            Self::Jump(false, _, _) => "jz",
+            Self::Less(_, _, _) => "less",
+            Self::Equal(_, _, _) => "equal",
+            Self::Rebase(_) => "rebase",
+            Self::Halt => "halt",
+            Self::Ext(spec, _) => spec.mnemonic,
+        }
+    }
+
+    // parameter modes in the order the parameters follow the opcode
+    pub fn modes(&self) -> Vec<Mode> {
+        match *self {
+            Self::Add(a, b, dst) | Self::Mul(a, b, dst) => vec![a, b, dst],
+            Self::Less(a, b, dst) | Self::Equal(a, b, dst) => vec![a, b, dst],
+            Self::Jump(_, a, dst) => vec![a, dst],
+            Self::Read(m) | Self::Write(m) | Self::Rebase(m) => vec![m],
+            Self::Halt => vec![],
+            Self::Ext(spec, m) => m[..spec.params.len()].to_vec(),
+        }
+    }
+
+    // number of words taken by the instruction, opcode included
+    pub fn size(&self) -> usize {
+        1 + self.modes().len()
+    }
+}
+
+// comma separated words, as in the puzzle inputs
+pub fn parse(s: &str) -> Result<Vec<isize>, ParseIntError> {
+    parse_words(s)
+}
+
+pub fn parse_words<W: Word>(s: &str) -> Result<Vec<W>, W::Err> {
+    s.trim().split(',').map(|w| W::from_str(w.trim())).collect()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VmError {
+    InvalidOpcode { pc: usize, op_code: isize },
+    NegativeAddress { pc: usize, addr: isize },
+    JumpOutOfRange { pc: usize, target: isize },
+    // an address, jump target or op code too wide for isize
+    OutOfRange { pc: usize },
+    InputClosed { pc: usize },
+    OutputClosed { pc: usize },
+    WriteToImmediate { pc: usize },
+    Overflow { pc: usize },
+    StepLimit { pc: usize, steps: usize },
+    Timeout { pc: usize },
+    Loop { pc: usize },
+    // raised by an extension's callback
+    Extension { pc: usize, msg: &'static str },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidOpcode { pc, op_code } => write!(f, "invalid op code {} at {}", op_code, pc),
+            Self::NegativeAddress { pc, addr } => write!(f, "negative address {} at {}", addr, pc),
+            Self::JumpOutOfRange { pc, target } => write!(f, "jump to {} out of range at {}", target, pc),
+            Self::OutOfRange { pc } => write!(f, "value out of range at {}", pc),
+            Self::InputClosed { pc } => write!(f, "input closed at {}", pc),
+            Self::OutputClosed { pc } => write!(f, "output closed at {}", pc),
+            Self::WriteToImmediate { pc } => write!(f, "write to immediate parameter at {}", pc),
+            Self::Overflow { pc } => write!(f, "arithmetic overflow at {}", pc),
+            Self::StepLimit { pc, steps } => write!(f, "gave up after {} steps at {}", steps, pc),
+            Self::Timeout { pc } => write!(f, "timed out at {}", pc),
+            Self::Loop { pc } => write!(f, "infinite loop at {}", pc),
+            Self::Extension { pc, msg } => write!(f, "{} at {}", msg, pc),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status<W = isize> {
+    Running,
+    NeedsInput,
+    Output(W),
+    Watch(Hit<W>),
+    Halted,
 }
 
 #[derive(Debug)]
-pub struct VM {
-    pub mem: Vec<isize>,
+pub struct VM<W = isize> {
+    pub mem: Memory<W>,
     pub pc: usize,
+    pub base: isize,
     pub halt: bool,
-    reader: Receiver<isize>,
-    writer: SyncSender<isize>,
+    // applies to add and mul; the relative base is always checked
+    pub overflow: Overflow,
+    // address of the instruction currently executing, for error reports
+    op_pc: usize,
+    input: VecDeque<W>,
+    // without channels the VM yields on IO instead of blocking
+    reader: Option<Receiver<W>>,
+    writer: Option<SyncSender<W>>,
+    watches: Watches<W>,
+    trace: Option<Trace<W>>,
+    loops: Option<LoopCheck>,
+    profile: Option<Profile>,
+    decoded: Option<DecodeCache<W>>,
+    extensions: Extensions<W>,
 }
 
 impl VM {
     pub fn new(program: &[isize]) -> Self {
-        Self::with_io(program).0
+        Self::from_words(program)
     }
+}
 
-    pub fn with_io(program: &[isize]) -> (Self, SyncSender<isize>, Receiver<isize>) {
-        let (input_tx, input_rx): (SyncSender<isize>, Receiver<isize>) = mpsc::sync_channel(0);
-        let (output_tx, output_rx): (SyncSender<isize>, Receiver<isize>) = mpsc::sync_channel(0);
-        let s = Self {
-            mem: program.to_vec(),
+impl<W: Word> VM<W> {
+    // for word types other than isize, e.g. VM::<i128>::from_words
+    pub fn from_words(program: &[W]) -> Self {
+        Self {
+            mem: Memory::new(program),
             pc: 0,
+            base: 0,
             halt: false,
-            reader: input_rx,
-            writer: output_tx,
-        };
+            overflow: Overflow::default(),
+            op_pc: 0,
+            input: VecDeque::new(),
+            reader: None,
+            writer: None,
+            watches: Watches::default(),
+            trace: None,
+            loops: None,
+            profile: None,
+            decoded: None,
+            extensions: Extensions::default(),
+        }
+    }
+
+    pub fn with_io(program: &[W]) -> (Self, SyncSender<W>, Receiver<W>) {
+        let (input_tx, input_rx): (SyncSender<W>, Receiver<W>) = mpsc::sync_channel(0);
+        let (output_tx, output_rx): (SyncSender<W>, Receiver<W>) = mpsc::sync_channel(0);
+        let mut s = Self::from_words(program);
+        s.reader = Some(input_rx);
+        s.writer = Some(output_tx);
         (s, input_tx, output_rx)
     }
 
-    pub fn run(&mut self) {
+    // queued input is consumed before anything arriving on the channel
+    pub fn push_input(&mut self, value: W) {
+        self.input.push_back(value);
+    }
+
+    // accesses to watched addresses stop the VM after the instruction that
+    // made them, unless a callback is installed
+    pub fn watch(&mut self, range: RangeInclusive<usize>, access: Access) {
+        self.watches.add(range, access);
+    }
+
+    pub fn unwatch(&mut self, addr: usize) -> bool {
+        self.watches.remove(addr)
+    }
+
+    pub fn on_watch<F: FnMut(&Hit<W>) + Send + 'static>(&mut self, f: F) {
+        self.watches.set_callback(Some(Box::new(f)));
+    }
+
+    // runs until the VM halts, needs input, produces output or hits a
+    // watchpoint; with channels attached only Halted is ever returned
+    pub fn run(&mut self) -> Result<Status<W>, VmError> {
         loop {
-            if self.halt {
-                return;
+            match self.step()? {
+                Status::Running => (),
+                status => return Ok(status),
             }
-
-            self.step();
         }
     }
 
-    pub fn step(&mut self) {
+    // on error or NeedsInput the pc is left on the current instruction
+    pub fn step(&mut self) -> Result<Status<W>, VmError> {
+        if let Some(hit) = self.watches.next_hit() {
+            return Ok(Status::Watch(hit));
+        }
         if self.halt {
-            return;
+            return Ok(Status::Halted);
         }
 
-        let op_code = self.mem[self.pc];
-        let op = Op::from(op_code);
+        self.op_pc = self.pc;
+        let op = self.fetch()?;
+        self.check_loop(op)?;
         self.pc += 1;
+        if let Some(t) = &mut self.trace {
+            t.begin(self.op_pc, op, self.base);
+        }
 
+        let result = self.exec(op);
+        match result {
+            Ok(Status::NeedsInput) | Err(_) => {
+                self.pc = self.op_pc;
+                self.watches.discard();
+                if let Some(t) = &mut self.trace {
+                    t.discard();
+                }
+                return result;
+            },
+            _ => {
+                self.watches.finish(self.op_pc, op);
+                if let Some(t) = &mut self.trace {
+                    t.finish();
+                }
+                if let Some(p) = &mut self.profile {
+                    p.record(self.op_pc, op);
+                }
+            },
+        }
+        match (result, self.watches.next_hit()) {
+            (Ok(Status::Running), Some(hit)) => Ok(Status::Watch(hit)),
+            (Ok(Status::Halted), Some(hit)) => Ok(Status::Watch(hit)),
+            (result, Some(hit)) => {
+                self.watches.push_front(hit);
+                result
+            },
+            (result, None) => result,
+        }
+    }
+
+    fn exec(&mut self, op: Op) -> Result<Status<W>, VmError> {
         match op {
-            Some(Op::Add(_, _)) => self.binop(op.unwrap()),
-            Some(Op::Mul(_, _)) => self.binop(op.unwrap()),
-            Some(Op::Less(_, _)) => self.cmpop(op.unwrap()),
-            Some(Op::Equal(_, _)) => self.cmpop(op.unwrap()),
-            Some(Op::Read) => {
-                let ptr = self.mem[self.pc];
+            Op::Add(_, _, _) | Op::Mul(_, _, _) => self.binop(op)?,
+            Op::Less(_, _, _) | Op::Equal(_, _, _) => self.cmpop(op)?,
+            Op::Read(dst_mode) => {
+                let ptr = self.mem.get(self.pc);
                 self.pc += 1;
-                self.put(ptr, self.reader.recv().unwrap());
+                let value = match (self.input.pop_front(), &self.reader) {
+                    (Some(value), _) => value,
+                    (None, Some(reader)) => reader.recv()
+                        .map_err(|_| VmError::InputClosed { pc: self.op_pc })?,
+                    (None, None) => return Ok(Status::NeedsInput),
+                };
+                if let Err(e) = self.put(&ptr, dst_mode, value.clone()) {
+                    self.input.push_front(value);
+                    return Err(e);
+                }
+                if let Some(t) = &mut self.trace {
+                    t.input(&value);
+                }
             },
-            Some(Op::Write(mode)) => {
-                let ptr = self.mem[self.pc];
+            Op::Write(mode) => {
+                let ptr = self.mem.get(self.pc);
                 self.pc += 1;
-                self.writer.send(self.deref(ptr, mode)).unwrap();
+                let value = self.deref(&ptr, mode)?;
+                if let Some(t) = &mut self.trace {
+                    t.output(&value);
+                }
+                match &self.writer {
+                    Some(writer) => writer.send(value)
+                        .map_err(|_| VmError::OutputClosed { pc: self.op_pc })?,
+                    None => return Ok(Status::Output(value)),
+                }
             },
-            Some(Op::Jump(m, a_mode, dst_mode)) => {
-                let ptr = self.mem[self.pc];
-                let val = self.deref(ptr, a_mode);
-                let dst_ptr = self.mem[self.pc + 1];
-                let dst = self.deref(dst_ptr, dst_mode);
-                if (val != 0) == m {
-                    self.pc = usize::try_from(dst).unwrap();
+            Op::Jump(m, a_mode, dst_mode) => {
+                let ptr = self.mem.get(self.pc);
+                let val = self.deref(&ptr, a_mode)?;
+                let dst_ptr = self.mem.get(self.pc + 1);
+                let dst = self.deref(&dst_ptr, dst_mode)?;
+                if val.is_zero() != m {
+                    let target = self.isize(&dst)?;
+                    self.pc = usize::try_from(target)
+                        .map_err(|_| VmError::JumpOutOfRange { pc: self.op_pc, target })?;
                 } else {
                     self.pc += 2;
                 }
             },
-            Some(Op::Halt) => {
-                self.halt = true;
+            Op::Rebase(mode) => {
+                let ptr = self.mem.get(self.pc);
+                self.pc += 1;
+                let offset = self.deref(&ptr, mode)?;
+                let offset = self.isize(&offset)?;
+                self.base = self.base.checked_add(offset)
+                    .ok_or(VmError::Overflow { pc: self.op_pc })?;
             },
-            None => {
-                eprintln!("invalid op code {}", op_code);
+            Op::Halt => {
+                self.halt = true;
+                return Ok(Status::Halted);
             },
+            Op::Ext(spec, modes) => self.extension(spec, modes)?,
         }
+        Ok(Status::Running)
+    }
+
+    #[inline(always)]
+    fn isize(&self, x: &W) -> Result<isize, VmError> {
+        x.to_isize().ok_or(VmError::OutOfRange { pc: self.op_pc })
+    }
+
+    #[inline(always)]
+    fn addr(&self, ptr: &W, mode: Mode) -> Result<usize, VmError> {
+        let addr = match mode {
+            Mode::Rel => self.base.checked_add(self.isize(ptr)?)
+                .ok_or(VmError::OutOfRange { pc: self.op_pc })?,
+            _ => self.isize(ptr)?,
+        };
+        usize::try_from(addr).map_err(|_| VmError::NegativeAddress { pc: self.op_pc, addr })
     }
 
     #[inline(always)]
-    fn put(&mut self, ptr: isize, value: isize) {
-        self.mem[usize::try_from(ptr).unwrap()] = value;
+    fn put(&mut self, ptr: &W, mode: Mode, value: W) -> Result<(), VmError> {
+        if mode == Mode::Imm {
+            return Err(VmError::WriteToImmediate { pc: self.op_pc });
+        }
+        let addr = self.addr(ptr, mode)?;
+        if !self.watches.is_empty() {
+            self.watches.record(addr, Access::Write, &self.mem[addr], &value);
+        }
+        if let Some(t) = &mut self.trace {
+            t.write(addr, &self.mem[addr], &value);
+        }
+        if let Some(l) = &mut self.loops {
+            l.write(addr, &self.mem[addr], &value);
+        }
+        self.mem[addr] = value;
+        Ok(())
     }
 
     #[inline(always)]
-    fn deref(&self, ptr: isize, mode: Mode) -> isize {
-        match mode {
-            Mode::Ptr => self.mem[usize::try_from(ptr).unwrap()],
-            Mode::Imm => ptr,
+    fn deref(&mut self, ptr: &W, mode: Mode) -> Result<W, VmError> {
+        if mode == Mode::Imm {
+            if let Some(t) = &mut self.trace {
+                t.read(ptr);
+            }
+            return Ok(ptr.clone());
+        }
+        let addr = self.addr(ptr, mode)?;
+        let value = self.mem.get(addr);
+        if !self.watches.is_empty() {
+            self.watches.record(addr, Access::Read, &value, &value);
+        }
+        if let Some(t) = &mut self.trace {
+            t.read(&value);
         }
+        Ok(value)
     }
 
-    fn binop(&mut self, op: Op) {
-        let a_ptr = self.mem[self.pc];
-        let b_ptr = self.mem[self.pc + 1];
-        let dst_ptr = self.mem[self.pc + 2];
+    fn binop(&mut self, op: Op) -> Result<(), VmError> {
+        let a_ptr = self.mem.get(self.pc);
+        let b_ptr = self.mem.get(self.pc + 1);
+        let dst_ptr = self.mem.get(self.pc + 2);
         self.pc += 3;
-        
+
         let result = match op {
-            Op::Add(a_mode, b_mode) => {
-                let a = self.deref(a_ptr, a_mode);
-                let b = self.deref(b_ptr, b_mode);
-                a + b
+            Op::Add(a_mode, b_mode, _) => {
+                let a = self.deref(&a_ptr, a_mode)?;
+                let b = self.deref(&b_ptr, b_mode)?;
+                a.add(&b, self.overflow)
             },
-            Op::Mul(a_mode, b_mode) => {
-                let a = self.deref(a_ptr, a_mode);
-                let b = self.deref(b_ptr, b_mode);
-                a * b
+            Op::Mul(a_mode, b_mode, _) => {
+                let a = self.deref(&a_ptr, a_mode)?;
+                let b = self.deref(&b_ptr, b_mode)?;
+                a.mul(&b, self.overflow)
             },
             _ => panic!("unhandled binop"),
         };
-        self.put(dst_ptr, result);
+        let result = result.ok_or(VmError::Overflow { pc: self.op_pc })?;
+        self.put(&dst_ptr, op.dst().unwrap(), result)
     }
 
-    fn cmpop(&mut self, op: Op) {
-        let a_ptr = self.mem[self.pc];
-        let b_ptr = self.mem[self.pc + 1];
-        let dst_ptr = self.mem[self.pc + 2];
+    fn cmpop(&mut self, op: Op) -> Result<(), VmError> {
+        let a_ptr = self.mem.get(self.pc);
+        let b_ptr = self.mem.get(self.pc + 1);
+        let dst_ptr = self.mem.get(self.pc + 2);
         self.pc += 3;
-        
+
         let cmp = match op {
-            Op::Less(a_mode, b_mode) => {
-                let a = self.deref(a_ptr, a_mode);
-                let b = self.deref(b_ptr, b_mode);
+            Op::Less(a_mode, b_mode, _) => {
+                let a = self.deref(&a_ptr, a_mode)?;
+                let b = self.deref(&b_ptr, b_mode)?;
                 a < b
             },
-            Op::Equal(a_mode, b_mode) => {
-                let a = self.deref(a_ptr, a_mode);
-                let b = self.deref(b_ptr, b_mode);
+            Op::Equal(a_mode, b_mode, _) => {
+                let a = self.deref(&a_ptr, a_mode)?;
+                let b = self.deref(&b_ptr, b_mode)?;
                 a == b
             },
             _ => panic!("unhandled cmp op"),
         };
-        let result = if cmp { 1 } else { 0 };
-        self.put(dst_ptr, result);
+        let result = W::from_isize(if cmp { 1 } else { 0 });
+        self.put(&dst_ptr, op.dst().unwrap(), result)
     }
 }
 
@@ -244,13 +614,13 @@ mod tests {
         ];
         let mut vm = VM::new(&prog);
         
-        vm.step();
+        vm.step().unwrap();
         assert_eq!(vm.mem[3], 70);
 
-        vm.step();
+        vm.step().unwrap();
         assert_eq!(vm.mem[0], 3500);
 
-        vm.step();
+        vm.step().unwrap();
         assert!(vm.halt);
     }
 
@@ -272,9 +642,9 @@ mod tests {
             responses
         });
         let vm = thread::spawn(move || vm.run());
-        
+
         i.join().expect("input thread panicked");
-        vm.join().expect("vm thread panicked");
+        vm.join().expect("vm thread panicked").unwrap();
         o.join().expect("output thread panicked")
     }
 
@@ -297,13 +667,67 @@ mod tests {
         ];
         let mut vm = VM::new(&prog);
 
-        vm.step();
+        vm.step().unwrap();
         assert_eq!(vm.mem[4], 99);
 
-        vm.step();
+        vm.step().unwrap();
         assert!(vm.halt);
     }
 
+    #[test]
+    fn rel_modes() {
+        let prog = vec![
+            109, 10,
+            21101, 2, 3, -1,
+            209, -6,
+            99,
+            0,
+        ];
+        let mut vm = VM::new(&prog);
+
+        vm.step().unwrap();
+        assert_eq!(vm.base, 10);
+
+        vm.step().unwrap();
+        assert_eq!(vm.mem[9], 5);
+
+        vm.step().unwrap();
+        assert_eq!(vm.base, 13);
+
+        vm.step().unwrap();
+        assert!(vm.halt);
+    }
+
+    #[test]
+    fn rel_io() {
+        let prog = vec![
+            109, 7,
+            203, 0,
+            204, 0,
+            99,
+            0,
+        ];
+        let result = test_io(&prog, vec![123])[0];
+        assert_eq!(result, 123);
+    }
+
+    #[test]
+    fn mem_beyond_image() {
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let result = test_io(&quine, vec![]);
+        assert_eq!(result, quine);
+
+        let prog = vec![
+            1101, 6, 7, 1000000000000,
+            4, 1000000000000,
+            99,
+        ];
+        let result = test_io(&prog, vec![]);
+        assert_eq!(result, vec![13]);
+    }
+
     #[test]
     fn jump_ptr() {
         let prog = vec![
@@ -340,6 +764,24 @@ mod tests {
         assert_eq!(result, 0);
     }
 
+    #[test]
+    fn jump_rel() {
+        let prog = vec![
+            109, 14,
+            203, 0,
+            1205, 0, 10,
+            104, 0, 99,
+            104, 1, 99,
+            0, 0,
+        ];
+
+        let result = test_io(&prog, vec![123])[0];
+        assert_eq!(result, 1);
+
+        let result = test_io(&prog, vec![0])[0];
+        assert_eq!(result, 0);
+    }
+
     #[test]
     fn cmp_ptr() {
         let mut prog = vec![
@@ -416,4 +858,164 @@ mod tests {
         let result = test_io(&prog, vec![9])[0];
         assert_eq!(result, 1001);
     }
+
+    #[test]
+    fn errors() {
+        let mut vm = VM::new(&[98]);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode { pc: 0, op_code: 98 }));
+        assert_eq!(vm.pc, 0);
+        assert!(!vm.halt);
+
+        let mut vm = VM::new(&[1101, 1, 1, 6, 1, 7, 0, -1]);
+        vm.step().unwrap();
+        assert_eq!(vm.step(), Err(VmError::NegativeAddress { pc: 4, addr: -1 }));
+        assert_eq!(vm.pc, 4);
+
+        let mut vm = VM::new(&[1105, 1, -5]);
+        assert_eq!(vm.step(), Err(VmError::JumpOutOfRange { pc: 0, target: -5 }));
+
+        let (mut vm, _, _) = VM::<isize>::with_io(&[3, 0, 99]);
+        assert_eq!(vm.run(), Err(VmError::InputClosed { pc: 0 }));
+
+        let (mut vm, _, _) = VM::<isize>::with_io(&[104, 1, 99]);
+        assert_eq!(vm.run(), Err(VmError::OutputClosed { pc: 0 }));
+    }
+
+    #[test]
+    fn resumable() {
+        let prog = vec![
+            3, 9,
+            1002, 9, 2, 9,
+            4, 9,
+            99,
+            0,
+        ];
+        let mut vm = VM::new(&prog);
+
+        assert_eq!(vm.run(), Ok(Status::NeedsInput));
+        assert_eq!(vm.pc, 0);
+        assert_eq!(vm.run(), Ok(Status::NeedsInput));
+
+        vm.push_input(21);
+        assert_eq!(vm.run(), Ok(Status::Output(42)));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn cooperative() {
+        // each machine doubles its input; chain three on one thread
+        let prog = vec![
+            3, 9,
+            1002, 9, 2, 9,
+            4, 9,
+            99,
+            0,
+        ];
+        let mut vms: Vec<VM> = (0..3).map(|_| VM::new(&prog)).collect();
+        let mut signal = 5;
+        for vm in vms.iter_mut() {
+            vm.push_input(signal);
+            signal = match vm.run().unwrap() {
+                Status::Output(x) => x,
+                status => panic!("unexpected {:?}", status),
+            };
+        }
+        assert_eq!(signal, 40);
+        assert!(vms.iter_mut().all(|vm| vm.run() == Ok(Status::Halted)));
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(Op::from(2), Some(Op::Mul(Mode::Ptr, Mode::Ptr, Mode::Ptr)));
+        assert_eq!(Op::from(1205), Some(Op::Jump(true, Mode::Rel, Mode::Imm)));
+        assert_eq!(Op::from(10001), Some(Op::Add(Mode::Ptr, Mode::Ptr, Mode::Imm)));
+        assert_eq!(Op::from(11101), Some(Op::Add(Mode::Imm, Mode::Imm, Mode::Imm)));
+        assert_eq!(Op::from(203), Some(Op::Read(Mode::Rel)));
+        assert!(!Op::from(10001).unwrap().is_valid());
+        assert!(!Op::from(103).unwrap().is_valid());
+        assert!(Op::from(1205).unwrap().is_valid());
+
+        assert_eq!(Op::from(0), None);
+        assert_eq!(Op::from(-1), None);
+        assert_eq!(Op::from(301), None);
+        assert_eq!(Op::from(1003), None);
+        assert_eq!(Op::from(199), None);
+        assert_eq!(Op::from(100001), None);
+    }
+
+    #[test]
+    fn encode() {
+        let mut count = 0;
+        for x in 0..30000 {
+            if let Some(op) = Op::from(x) {
+                assert_eq!(op.encode(), x);
+                assert_eq!(Op::new(op.code(), &op.modes()), Some(op));
+                count += 1;
+            }
+        }
+        // 4 ternary ops * 27, 2 jumps * 9, 3 unary ops * 3, halt
+        assert_eq!(count, 4 * 27 + 2 * 9 + 3 * 3 + 1);
+        assert_eq!(Op::Less(Mode::Rel, Mode::Imm, Mode::Rel).encode(), 21207);
+    }
+
+    #[test]
+    fn write_to_immediate() {
+        let mut vm = VM::new(&[11101, 1, 1, 0, 99]);
+        assert_eq!(vm.run(), Err(VmError::WriteToImmediate { pc: 0 }));
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn overflow() {
+        let prog = [1002, 5, 2, 5, 99, isize::MAX];
+        let mut vm = VM::new(&prog);
+        assert_eq!(vm.run(), Err(VmError::Overflow { pc: 0 }));
+        assert_eq!((vm.pc, vm.mem[5]), (0, isize::MAX));
+
+        let mut vm = VM::new(&prog);
+        vm.overflow = Overflow::Wrapping;
+        assert_eq!(vm.run(), Ok(Status::Halted));
+        assert_eq!(vm.mem[5], -2);
+
+        let mut vm = VM::new(&prog);
+        vm.overflow = Overflow::Saturating;
+        assert_eq!(vm.run(), Ok(Status::Halted));
+        assert_eq!(vm.mem[5], isize::MAX);
+
+        // the relative base never wraps
+        let mut vm = VM::new(&[109, isize::MAX, 109, 1, 99]);
+        vm.overflow = Overflow::Wrapping;
+        assert_eq!(vm.run(), Err(VmError::Overflow { pc: 2 }));
+    }
+
+    #[test]
+    fn wide_words() {
+        let mut vm = VM::<i128>::from_words(&[2, 7, 7, 7, 4, 7, 99, 1 << 40]);
+        assert_eq!(vm.run(), Ok(Status::Output(1 << 80)));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+
+        let mut vm = VM::<i64>::from_words(&[2, 7, 7, 7, 4, 7, 99, 1 << 40]);
+        assert_eq!(vm.run(), Err(VmError::Overflow { pc: 0 }));
+
+        // values only need to fit in isize where they're used as addresses
+        let mut vm = VM::<i128>::from_words(&[1105, 1, 1 << 100]);
+        assert_eq!(vm.run(), Err(VmError::OutOfRange { pc: 0 }));
+        let mut vm = VM::<i128>::from_words(&[1 << 100]);
+        assert_eq!(vm.run(), Err(VmError::OutOfRange { pc: 0 }));
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn big_words() {
+        use bigint::BigInt;
+        let prog: Vec<BigInt> = [2, 11, 11, 11, 2, 11, 11, 11, 4, 11, 99, 1 << 40].iter()
+            .map(|x| BigInt::from(*x))
+            .collect();
+        let mut vm = VM::from_words(&prog);
+        match vm.run() {
+            Ok(Status::Output(x)) => assert_eq!(x.to_string(), "1461501637330902918203684832716283019655932542976"),
+            status => panic!("unexpected {:?}", status),
+        }
+    }
 }
@@ -0,0 +1,624 @@
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::cfg::Cfg;
+use super::{Mode, Op, Status, VM, VmError};
+
+// Translated code covers the basic blocks found by Cfg. A block is entered at
+// any of its instructions, but only while memory from there to the end of the
+// block still holds the words it was translated from, and a store into that
+// stretch ends the block. Everything translated code doesn't do itself is
+// left to VM::step: other code, input, halt, faults and overflow, so errors
+// and the overflow policy behave exactly as when interpreting.
+pub trait Native {
+    // runs translated code from vm.pc, returning an output with the pc past
+    // the write, or None with the pc on an instruction for the interpreter
+    fn enter(&self, vm: &mut VM) -> Option<isize>;
+
+    // like VM::run. Watches, traces, profiles, loop checks and channels only
+    // work with the interpreter, so a VM using any of them is interpreted.
+    fn run(&self, vm: &mut VM) -> Result<Status, VmError> {
+        if hooked(vm) {
+            return vm.run();
+        }
+        loop {
+            if let Some(x) = self.enter(vm) {
+                return Ok(Status::Output(x));
+            }
+            match vm.step()? {
+                Status::Running => (),
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+// functions generated by rust_source
+impl<F: Fn(&mut VM) -> Option<isize>> Native for F {
+    fn enter(&self, vm: &mut VM) -> Option<isize> {
+        self(vm)
+    }
+}
+
+fn hooked(vm: &VM) -> bool {
+    vm.halt
+        || !vm.watches.is_empty()
+        || vm.trace.is_some()
+        || vm.loops.is_some()
+        || vm.profile.is_some()
+        || vm.reader.is_some()
+        || vm.writer.is_some()
+}
+
+// whether memory from `from` to the end of the block starting at `start`
+// still holds the block's words
+pub fn unchanged(vm: &VM, from: usize, start: usize, words: &[isize]) -> bool {
+    words[from - start..].iter()
+        .zip(from..)
+        .all(|(w, addr)| vm.mem[addr] == *w)
+}
+
+// the address of a relative parameter, if valid
+pub fn rel(vm: &VM, offset: isize) -> Option<usize> {
+    usize::try_from(vm.base.checked_add(offset)?).ok()
+}
+
+// a jump target read from memory, if valid
+pub fn addr(x: isize) -> Option<usize> {
+    usize::try_from(x).ok()
+}
+
+// a parameter as known at translation time
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    Imm(isize),
+    Ptr(usize),
+    Rel(isize),
+    // a negative address, which the interpreter reports
+    Fault,
+}
+
+impl Operand {
+    fn new(mode: Mode, x: isize) -> Self {
+        match mode {
+            Mode::Imm => Operand::Imm(x),
+            Mode::Ptr => usize::try_from(x).map_or(Operand::Fault, Operand::Ptr),
+            Mode::Rel => Operand::Rel(x),
+        }
+    }
+
+    fn load(self, vm: &VM) -> Option<isize> {
+        match self {
+            Operand::Imm(x) => Some(x),
+            Operand::Ptr(a) => Some(vm.mem.get(a)),
+            Operand::Rel(offset) => Some(vm.mem.get(rel(vm, offset)?)),
+            Operand::Fault => None,
+        }
+    }
+
+    // the address written, or None if nothing was
+    fn store(self, vm: &mut VM, value: isize) -> Option<usize> {
+        let a = match self {
+            Operand::Ptr(a) => a,
+            Operand::Rel(offset) => rel(vm, offset)?,
+            Operand::Imm(_) | Operand::Fault => return None,
+        };
+        vm.mem[a] = value;
+        Some(a)
+    }
+}
+
+// an instruction of a translated block
+struct Instr {
+    pc: usize,
+    next: usize,
+    op: Op,
+    params: Vec<Operand>,
+}
+
+// what the interpreter has to do for itself
+fn interpreted(i: &Instr) -> bool {
+    matches!(i.op, Op::Read(_) | Op::Halt | Op::Ext(_, _)) || i.params.contains(&Operand::Fault)
+}
+
+// translatable blocks, each as its first address, end and instructions
+fn blocks(program: &[isize]) -> Vec<(usize, usize, Vec<Instr>)> {
+    Cfg::new(program).blocks.values()
+        .map(|b| {
+            let instrs = b.lines.iter()
+                .map(|line| {
+                    let op = line.op.unwrap();
+                    let params = op.modes().into_iter()
+                        .zip(&line.words[1..])
+                        .map(|(m, x)| Operand::new(m, *x))
+                        .collect();
+                    Instr { pc: line.addr, next: line.addr + op.size(), op, params }
+                })
+                .collect();
+            (b.start, b.end, instrs)
+        })
+        .collect()
+}
+
+enum Flow {
+    Next,
+    Jump(usize),
+    Output(isize),
+    // executed, with the pc set for the interpreter
+    Exit,
+    // not executed, with the pc on the instruction
+    Interpret,
+}
+
+type Step = Box<dyn Fn(&mut VM) -> Flow + Send + Sync>;
+
+struct Block {
+    start: usize,
+    end: usize,
+    words: Vec<isize>,
+    steps: Vec<(usize, Step)>,
+}
+
+fn step(i: &Instr, end: usize) -> Step {
+    let (pc, next) = (i.pc, i.next);
+    if interpreted(i) {
+        return Box::new(move |vm| {
+            vm.pc = pc;
+            Flow::Interpret
+        });
+    }
+    let p = i.params.clone();
+    // a store into the rest of the block leaves the rest to the interpreter
+    let stored = move |vm: &mut VM, a: Option<usize>| match a {
+        None => {
+            vm.pc = pc;
+            Flow::Interpret
+        },
+        Some(a) if a >= next && a < end => {
+            vm.pc = next;
+            Flow::Exit
+        },
+        Some(_) => Flow::Next,
+    };
+    match i.op {
+        Op::Add(_, _, _) | Op::Mul(_, _, _) | Op::Less(_, _, _) | Op::Equal(_, _, _) => {
+            let f: fn(isize, isize) -> Option<isize> = match i.op {
+                Op::Add(_, _, _) => |x, y| x.checked_add(y),
+                Op::Mul(_, _, _) => |x, y| x.checked_mul(y),
+                Op::Less(_, _, _) => |x, y| Some((x < y) as isize),
+                _ => |x, y| Some((x == y) as isize),
+            };
+            Box::new(move |vm| {
+                let v = match (p[0].load(vm), p[1].load(vm)) {
+                    (Some(x), Some(y)) => f(x, y),
+                    _ => None,
+                };
+                let a = v.and_then(|v| p[2].store(vm, v));
+                stored(vm, a)
+            })
+        },
+        Op::Write(_) => Box::new(move |vm| match p[0].load(vm) {
+            Some(x) => {
+                vm.pc = next;
+                Flow::Output(x)
+            },
+            None => {
+                vm.pc = pc;
+                Flow::Interpret
+            },
+        }),
+        // like the VM, the target is read whether or not the jump is taken
+        Op::Jump(m, _, _) => Box::new(move |vm| {
+            let target = match (p[0].load(vm), p[1].load(vm)) {
+                (Some(c), Some(t)) if (c != 0) == m => addr(t),
+                (Some(_), Some(_)) => Some(next),
+                _ => None,
+            };
+            match target {
+                Some(t) => Flow::Jump(t),
+                None => {
+                    vm.pc = pc;
+                    Flow::Interpret
+                },
+            }
+        }),
+        Op::Rebase(_) => Box::new(move |vm| match p[0].load(vm).and_then(|x| vm.base.checked_add(x)) {
+            Some(base) => {
+                vm.base = base;
+                Flow::Next
+            },
+            None => {
+                vm.pc = pc;
+                Flow::Interpret
+            },
+        }),
+        Op::Read(_) | Op::Halt | Op::Ext(_, _) => unreachable!(),
+    }
+}
+
+// the program translated to a chain of closures per basic block
+pub struct Compiled {
+    blocks: Vec<Block>,
+    // (block, step) by instruction address
+    entries: Vec<Option<(usize, usize)>>,
+}
+
+impl Compiled {
+    pub fn new(program: &[isize]) -> Self {
+        let mut compiled = Compiled { blocks: Vec::new(), entries: vec![None; program.len()] };
+        for (start, end, instrs) in blocks(program) {
+            for (i, instr) in instrs.iter().enumerate() {
+                compiled.entries[instr.pc] = Some((compiled.blocks.len(), i));
+            }
+            compiled.blocks.push(Block {
+                start,
+                end,
+                words: program[start..end].to_vec(),
+                steps: instrs.iter().map(|i| (i.pc, step(i, end))).collect(),
+            });
+        }
+        compiled
+    }
+
+    // number of instructions translated
+    pub fn len(&self) -> usize {
+        self.blocks.iter().map(|b| b.steps.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    // like enter, but executes at most `steps` instructions, counting down
+    pub fn enter_for(&self, vm: &mut VM, steps: &mut usize) -> Option<isize> {
+        'blocks: loop {
+            let (b, i) = self.entries.get(vm.pc).copied().flatten()?;
+            let block = &self.blocks[b];
+            if !unchanged(vm, vm.pc, block.start, &block.words) {
+                return None;
+            }
+            for (pc, step) in &block.steps[i..] {
+                if *steps == 0 {
+                    vm.pc = *pc;
+                    return None;
+                }
+                let flow = step(vm);
+                if let Flow::Interpret = flow {
+                    return None;
+                }
+                *steps -= 1;
+                match flow {
+                    Flow::Next => (),
+                    Flow::Jump(t) => {
+                        vm.pc = t;
+                        continue 'blocks;
+                    },
+                    Flow::Output(x) => return Some(x),
+                    Flow::Exit | Flow::Interpret => return None,
+                }
+            }
+            vm.pc = block.end;
+        }
+    }
+}
+
+impl Native for Compiled {
+    fn enter(&self, vm: &mut VM) -> Option<isize> {
+        let mut steps = usize::MAX;
+        self.enter_for(vm, &mut steps)
+    }
+}
+
+// Rust source for `pub fn <name>(vm: &mut VM) -> Option<isize>`, the same
+// translation as Compiled, for use as a Native. `krate` is the path the
+// generated code uses for this crate, normally "advent2019".
+pub fn rust_source(program: &[isize], name: &str, krate: &str) -> String {
+    // entering at an instruction the interpreter runs gains nothing
+    let blocks: Vec<_> = blocks(program).into_iter()
+        .filter(|(_, _, instrs)| !instrs.iter().all(interpreted))
+        .collect();
+    let mut s = String::new();
+    writeln!(s, "// translated from a {} word intcode program by intcode::aot::rust_source;", program.len()).unwrap();
+    writeln!(s, "// anything else is left to the interpreter").unwrap();
+    writeln!(s, "#[allow(clippy::all, unreachable_code, unused_imports, unused_variables)]").unwrap();
+    writeln!(s, "pub fn {}(vm: &mut {}::intcode::VM) -> Option<isize> {{", name, krate).unwrap();
+    writeln!(s, "    use {}::intcode::aot::{{addr, rel, unchanged}};", krate).unwrap();
+    for (start, end, _) in &blocks {
+        let words: Vec<String> = program[*start..*end].iter().map(|x| x.to_string()).collect();
+        writeln!(s, "    const B{}: [isize; {}] = [{}];", start, words.len(), words.join(", ")).unwrap();
+    }
+    writeln!(s, "    loop {{").unwrap();
+    writeln!(s, "        let from = vm.pc;").unwrap();
+    writeln!(s, "        match from {{").unwrap();
+    for (start, end, instrs) in &blocks {
+        let pcs: Vec<String> = instrs.iter()
+            .filter(|i| !interpreted(i))
+            .map(|i| i.pc.to_string())
+            .collect();
+        writeln!(s, "            {} if unchanged(vm, from, {}, &B{}) => {{", pcs.join(" | "), start, start).unwrap();
+        // entering mid block skips everything before the entry point,
+        // including the first instruction
+        for i in instrs {
+            let line = super::disasm::line(program, i.pc, Some(i.op));
+            writeln!(s, "                if from <= {} {{", i.pc).unwrap();
+            writeln!(s, "                    // {}", line.text()).unwrap();
+            for stmt in statements(i, *end) {
+                writeln!(s, "                    {}", stmt).unwrap();
+            }
+            writeln!(s, "                }}").unwrap();
+        }
+        let last = instrs.last().unwrap();
+        if !interpreted(last) && !matches!(last.op, Op::Jump(_, _, _)) {
+            writeln!(s, "                vm.pc = {};", end).unwrap();
+        }
+        writeln!(s, "            }},").unwrap();
+    }
+    writeln!(s, "            _ => return None,").unwrap();
+    writeln!(s, "        }}").unwrap();
+    writeln!(s, "    }}").unwrap();
+    writeln!(s, "}}").unwrap();
+    s
+}
+
+// translates the program in `input` to `output`, as a build script would
+pub fn translate(input: &Path, output: &Path, name: &str, krate: &str) -> io::Result<()> {
+    let program = super::parse(&fs::read_to_string(input)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", input.display(), e)))?;
+    fs::write(output, rust_source(&program, name, krate))
+}
+
+fn bail(pc: usize) -> String {
+    format!("{{ vm.pc = {}; return None; }}", pc)
+}
+
+// a let statement loading a parameter
+fn load(name: &str, p: Operand, pc: usize) -> String {
+    match p {
+        Operand::Imm(x) => format!("let {} = {};", name, x),
+        Operand::Ptr(a) => format!("let {} = vm.mem.get({});", name, a),
+        Operand::Rel(offset) => format!(
+            "let {} = match rel(vm, {}) {{ Some(a) => vm.mem.get(a), None => {} }};", name, offset, bail(pc)),
+        Operand::Fault => unreachable!(),
+    }
+}
+
+fn statements(i: &Instr, end: usize) -> Vec<String> {
+    let (pc, next) = (i.pc, i.next);
+    if interpreted(i) {
+        return vec![format!("vm.pc = {};", pc), "return None;".to_string()];
+    }
+    let p = &i.params;
+    let mut out = Vec::new();
+    match i.op {
+        Op::Add(_, _, _) | Op::Mul(_, _, _) | Op::Less(_, _, _) | Op::Equal(_, _, _) => {
+            out.push(load("x", p[0], pc));
+            out.push(load("y", p[1], pc));
+            out.push(match i.op {
+                Op::Add(_, _, _) => format!("let v = match x.checked_add(y) {{ Some(v) => v, None => {} }};", bail(pc)),
+                Op::Mul(_, _, _) => format!("let v = match x.checked_mul(y) {{ Some(v) => v, None => {} }};", bail(pc)),
+                Op::Less(_, _, _) => "let v = (x < y) as isize;".to_string(),
+                _ => "let v = (x == y) as isize;".to_string(),
+            });
+            match p[2] {
+                Operand::Ptr(a) => {
+                    out.push(format!("vm.mem[{}] = v;", a));
+                    if a >= next && a < end {
+                        out.push(format!("vm.pc = {};", next));
+                        out.push("return None;".to_string());
+                    }
+                },
+                Operand::Rel(offset) => {
+                    out.push(format!("let a = match rel(vm, {}) {{ Some(a) => a, None => {} }};", offset, bail(pc)));
+                    out.push("vm.mem[a] = v;".to_string());
+                    if next < end {
+                        out.push(format!("if a >= {} && a < {} {{ vm.pc = {}; return None; }}", next, end, next));
+                    }
+                },
+                Operand::Imm(_) | Operand::Fault => unreachable!(),
+            }
+        },
+        Op::Write(_) => {
+            out.push(load("x", p[0], pc));
+            out.push(format!("vm.pc = {};", next));
+            out.push("return Some(x);".to_string());
+        },
+        Op::Jump(m, _, _) => {
+            out.push(load("c", p[0], pc));
+            if let Operand::Imm(_) = p[1] {
+                out.push(format!("if c {} 0 {{", if m { "!=" } else { "==" }));
+                match p[1] {
+                    Operand::Imm(t) if t >= 0 => out.push(format!("    vm.pc = {};", t)),
+                    _ => out.push(format!("    {}", bail(pc))),
+                }
+            } else {
+                out.push(load("t", p[1], pc));
+                out.push(format!("if c {} 0 {{", if m { "!=" } else { "==" }));
+                out.push(format!("    vm.pc = match addr(t) {{ Some(t) => t, None => {} }};", bail(pc)));
+            }
+            out.push("    continue;".to_string());
+            out.push("}".to_string());
+            out.push(format!("vm.pc = {};", next));
+        },
+        Op::Rebase(_) => {
+            out.push(load("x", p[0], pc));
+            out.push(format!("vm.base = match vm.base.checked_add(x) {{ Some(b) => b, None => {} }};", bail(pc)));
+        },
+        Op::Read(_) | Op::Halt | Op::Ext(_, _) => unreachable!(),
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::fuzz::{self, Engine};
+
+    // the checked in translations used by the benchmark, and one that is
+    // entered mid block
+    mod native {
+        use crate as advent2019;
+        include!("../../benches/native/day02.rs");
+        include!("../../benches/native/countdown.rs");
+        include!("../../benches/native/reentry.rs");
+    }
+
+    fn day02_input() -> Vec<isize> {
+        crate::intcode::parse(&crate::load("02.txt")).unwrap()
+    }
+
+    // as in the benchmark
+    fn countdown_program() -> Vec<isize> {
+        assemble("
+                    read [n]
+            loop:   add [n], -1, [n]
+                    mul [n], 3, [t]
+                    less [t], 10, [t]
+                    jnz [n], loop
+                    halt
+            n:      data 0
+            t:      data 0
+        ").unwrap()
+    }
+
+    // the write splits the loop's block, so each pass after the first
+    // enters it at the add that follows
+    fn reentry_program() -> Vec<isize> {
+        vec![3, 16, 1001, 16, -1, 16, 4, 16, 1001, 17, 1, 17, 1005, 16, 2, 99, 0, 0]
+    }
+
+    fn run_all<N: Native>(native: &N, program: &[isize], inputs: &[isize]) -> (Vec<isize>, Result<Status, VmError>, Vec<isize>) {
+        let mut vm = VM::new(program);
+        for x in inputs {
+            vm.push_input(*x);
+        }
+        let mut outputs = Vec::new();
+        let end = loop {
+            match native.run(&mut vm) {
+                Ok(Status::Output(x)) => outputs.push(x),
+                end => break end,
+            }
+        };
+        (outputs, end, vm.mem.to_vec())
+    }
+
+    fn interpret(program: &[isize], inputs: &[isize]) -> (Vec<isize>, Result<Status, VmError>, Vec<isize>) {
+        run_all(&|_: &mut VM| None, program, inputs)
+    }
+
+    #[test]
+    fn day02b() {
+        let mut program = day02_input();
+        let compiled = Compiled::new(&program);
+        for (noun, verb) in [(12, 2), (54, 85), (0, 0), (99, 99)].iter() {
+            program[1] = *noun;
+            program[2] = *verb;
+            let expected = interpret(&program, &[]);
+            assert_eq!(run_all(&compiled, &program, &[]), expected);
+            assert_eq!(run_all(&native::day02, &program, &[]), expected);
+        }
+    }
+
+    #[test]
+    fn countdown() {
+        let prog = countdown_program();
+        let expected = interpret(&prog, &[1000]);
+        assert_eq!(expected.1, Ok(Status::Halted));
+        assert_eq!(run_all(&Compiled::new(&prog), &prog, &[1000]), expected);
+        assert_eq!(run_all(&native::countdown, &prog, &[1000]), expected);
+    }
+
+    #[test]
+    fn checked_in_translations_are_current() {
+        let programs = [(day02_input(), "day02"), (countdown_program(), "countdown"), (reentry_program(), "reentry")];
+        for (prog, name) in programs.iter() {
+            let path = format!("benches/native/{}.rs", name);
+            assert_eq!(rust_source(prog, name, "advent2019"), fs::read_to_string(&path).unwrap(),
+                "regenerate {} with intcode::aot::rust_source", path);
+        }
+    }
+
+    #[test]
+    fn reentry() {
+        let prog = reentry_program();
+        let expected = interpret(&prog, &[6]);
+        assert_eq!(expected.0, vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(run_all(&Compiled::new(&prog), &prog, &[6]), expected);
+        assert_eq!(run_all(&native::reentry, &prog, &[6]), expected);
+    }
+
+    #[test]
+    fn loops_and_io() {
+        let prog = assemble("
+                    read [n]
+            loop:   mul [n], 2, [t]
+                    write [t]
+                    add [n], -1, [n]
+                    jnz [n], loop
+                    rebase 10
+                    add [rb-10], 1, [rb+1]
+                    write [rb+1]
+                    halt
+            n:      data 0
+            t:      data 0
+        ").unwrap();
+        let compiled = Compiled::new(&prog);
+        assert!(!compiled.is_empty());
+        let expected = interpret(&prog, &[3]);
+        // [rb-10] is the read's op code
+        assert_eq!(expected.0, vec![6, 4, 2, 4]);
+        assert_eq!(run_all(&compiled, &prog, &[3]), expected);
+    }
+
+    #[test]
+    fn self_modifying() {
+        // the loop turns the add into a mul, which translated code mustn't
+        // keep executing as an add
+        let prog = assemble("
+            op:     add [x], [x], [x]
+                    write [x]
+                    add [n], -1, [n]
+                    add [op], 1, [op]
+                    jnz [n], op
+                    halt
+            x:      data 3
+            n:      data 2
+        ").unwrap();
+        let compiled = Compiled::new(&prog);
+        let expected = interpret(&prog, &[]);
+        assert_eq!(expected.0, vec![6, 36]);
+        assert_eq!(run_all(&compiled, &prog, &[]), expected);
+
+        // and a store into the rest of its own block
+        let prog = assemble("
+                    add 1, 1, [x+1]
+            x:      add 0, 0, [y]
+                    write [y]
+                    halt
+            y:      data 0
+        ").unwrap();
+        assert_eq!(run_all(&Compiled::new(&prog), &prog, &[]).0, vec![2]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut vm = VM::new(&[1101, isize::MAX, 1, 5, 99, 0]);
+        assert_eq!(Compiled::new(&vm.mem.to_vec()).run(&mut vm), Err(VmError::Overflow { pc: 0 }));
+        let mut vm = VM::new(&[109, -5, 2101, 0, 0, 0, 99]);
+        assert_eq!(Compiled::new(&vm.mem.to_vec()).run(&mut vm), Err(VmError::NegativeAddress { pc: 2, addr: -5 }));
+    }
+
+    #[test]
+    fn random_programs() {
+        let mut rng = fuzz::Rng::new(23);
+        for _ in 0..300 {
+            let instructions = 1 + rng.below(24);
+            let case = fuzz::generate(&mut rng, instructions);
+            let expected = fuzz::run(Engine::Plain, &case, 2_000);
+            assert_eq!(fuzz::run(Engine::Compiled, &case, 2_000), expected, "{}", case);
+        }
+    }
+}
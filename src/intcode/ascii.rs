@@ -0,0 +1,145 @@
+use std::io::{self, BufRead, Write};
+
+use super::{Status, VM, VmError};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    pub text: String,
+    // anything outside the ASCII range, such as a final answer
+    pub values: Vec<isize>,
+    // NeedsInput or Halted
+    pub status: Status,
+}
+
+impl Output {
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.text.lines()
+    }
+}
+
+// wraps a VM whose IO is characters
+#[derive(Debug)]
+pub struct Ascii {
+    pub vm: VM,
+}
+
+impl Ascii {
+    pub fn new(vm: VM) -> Self {
+        Self { vm }
+    }
+
+    // queues the line's character codes followed by a newline
+    pub fn send(&mut self, line: &str) {
+        for c in line.chars().chain("\n".chars()) {
+            self.vm.push_input(c as isize);
+        }
+    }
+
+    // runs until the program wants input or halts
+    pub fn read(&mut self) -> Result<Output, VmError> {
+        let mut text = String::new();
+        let mut values = Vec::new();
+        loop {
+            match self.vm.run()? {
+                Status::Output(x) if (0..128).contains(&x) => text.push(x as u8 as char),
+                Status::Output(x) => values.push(x),
+                Status::Running | Status::Watch(_) => (),
+                status => return Ok(Output { text, values, status }),
+            }
+        }
+    }
+
+    // plays the program by hand: prints its output and sends each line
+    // typed until it halts or the input runs out
+    pub fn interact<R: BufRead, W: Write>(&mut self, mut input: R, mut out: W) -> io::Result<Result<Status, VmError>> {
+        loop {
+            let output = match self.read() {
+                Ok(output) => output,
+                Err(e) => return Ok(Err(e)),
+            };
+            write!(out, "{}", output.text)?;
+            for x in &output.values {
+                writeln!(out, "[{}]", x)?;
+            }
+            if output.status == Status::Halted {
+                return Ok(Ok(Status::Halted));
+            }
+            out.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(Ok(Status::NeedsInput));
+            }
+            self.send(line.trim_end_matches(['\r', '\n']));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // prints a prompt, then echoes lines upper-cased until one is empty,
+    // finishing with the number of characters seen
+    fn shout() -> VM {
+        let prog = assemble("
+                    write 62
+                    write 10
+            loop:   read [c]
+                    equal [c], 10, [t]
+                    jnz [t], eol
+                    add [n], 1, [n]
+                    less [c], 97, [t]
+                    jnz [t], out
+                    add [c], -32, [c]
+            out:    write [c]
+                    add 1, 0, [len]
+                    jz 0, loop
+            eol:    write 10
+                    jz [len], done
+                    add 0, 0, [len]
+                    jz 0, loop
+            done:   add [n], 1000, [n]
+                    write [n]
+                    halt
+            c:      data 0
+            t:      data 0
+            n:      data 0
+            len:    data 0
+        ").unwrap();
+        VM::new(&prog)
+    }
+
+    #[test]
+    fn lines() {
+        let mut term = Ascii::new(shout());
+        let out = term.read().unwrap();
+        assert_eq!(out.text, ">\n");
+        assert_eq!(out.status, Status::NeedsInput);
+
+        term.send("hello");
+        term.send("Intcode");
+        let out = term.read().unwrap();
+        assert_eq!(out.lines().collect::<Vec<&str>>(), vec!["HELLO", "INTCODE"]);
+
+        term.send("");
+        let out = term.read().unwrap();
+        assert_eq!(out.text, "\n");
+        assert_eq!(out.values, vec![1012]);
+        assert_eq!(out.status, Status::Halted);
+    }
+
+    #[test]
+    fn interactive() {
+        let mut term = Ascii::new(shout());
+        let mut screen = Vec::new();
+        let status = term.interact(&b"abc\r\nxyz\n\n"[..], &mut screen).unwrap();
+        assert_eq!(status, Ok(Status::Halted));
+        assert_eq!(String::from_utf8(screen).unwrap(), ">\nABC\nXYZ\n\n[1006]\n");
+
+        let mut term = Ascii::new(shout());
+        let mut screen = Vec::new();
+        assert_eq!(term.interact(&b"abc\n"[..], &mut screen).unwrap(), Ok(Status::NeedsInput));
+    }
+}
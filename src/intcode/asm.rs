@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use super::ext::Spec;
+use super::{Mode, Op};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl AsmError {
+    fn new(line: usize, msg: impl Into<String>) -> Self {
+        Self { line, msg: msg.into() }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for AsmError {}
+
+// a number, a label or a label with a constant offset
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(isize),
+    Label(String, isize),
+}
+
+#[derive(Clone, Debug)]
+struct Operand {
+    mode: Mode,
+    value: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Item {
+    Instr(Op, Vec<Operand>),
+    Data(Vec<Expr>),
+}
+
+const OPCODES: [isize; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+
+// the instruction for a mnemonic with every parameter in position mode
+fn lookup(mnemonic: &str, specs: &[Spec]) -> Option<Op> {
+    OPCODES.iter()
+        .filter_map(|code| Op::new(*code, &vec![Mode::Ptr; Op::arity(*code)?]))
+        .chain(specs.iter().filter_map(|s| s.op(&vec![Mode::Ptr; s.params.len()])))
+        .find(|op| op.mnemonic() == mnemonic)
+}
+
+// of the built in instructions
+pub(super) fn mnemonics() -> Vec<&'static str> {
+    OPCODES.iter()
+        .filter_map(|code| Op::new(*code, &vec![Mode::Ptr; Op::arity(*code)?]))
+        .map(|op| op.mnemonic())
+        .collect()
+}
+
+fn parse_expr(line: usize, s: &str) -> Result<Expr, AsmError> {
+    let s = s.trim();
+    if let Ok(x) = isize::from_str(s) {
+        return Ok(Expr::Num(x));
+    }
+    let (name, offset) = match s.find(['+', '-']) {
+        Some(i) => {
+            let offset = isize::from_str(&s[i..].replace([' ', '+'], ""))
+                .map_err(|_| AsmError::new(line, format!("invalid offset in `{}`", s)))?;
+            (s[..i].trim(), offset)
+        },
+        None => (s, 0),
+    };
+    let valid = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !valid || name == "rb" {
+        return Err(AsmError::new(line, format!("invalid operand `{}`", s)));
+    }
+    Ok(Expr::Label(name.to_string(), offset))
+}
+
+fn parse_operand(line: usize, s: &str) -> Result<Operand, AsmError> {
+    let s = s.trim();
+    if !(s.starts_with('[') && s.ends_with(']')) {
+        return Ok(Operand { mode: Mode::Imm, value: parse_expr(line, s)? });
+    }
+    let inner = s[1..s.len() - 1].trim();
+    if inner == "rb" {
+        return Ok(Operand { mode: Mode::Rel, value: Expr::Num(0) });
+    }
+    if let Some(rest) = inner.strip_prefix("rb") {
+        let rest = rest.trim();
+        // the sign and offset may be spaced out, as in [rb - 1]
+        if let Some(x) = rest.strip_prefix('+') {
+            return Ok(Operand { mode: Mode::Rel, value: parse_expr(line, x)? });
+        }
+        if let Some(x) = rest.strip_prefix('-') {
+            let value = match parse_expr(line, &format!("-{}", x.trim()))? {
+                Expr::Num(x) => Expr::Num(x),
+                Expr::Label(_, _) => return Err(AsmError::new(line, format!("invalid operand `{}`", s))),
+            };
+            return Ok(Operand { mode: Mode::Rel, value });
+        }
+    }
+    Ok(Operand { mode: Mode::Ptr, value: parse_expr(line, inner)? })
+}
+
+fn split_args(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').map(|a| a.trim()).collect()
+    }
+}
+
+fn parse_item(line: usize, s: &str, specs: &[Spec]) -> Result<Item, AsmError> {
+    let (mnemonic, rest) = match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, ""),
+    };
+    if mnemonic == "data" {
+        let values = split_args(rest).into_iter()
+            .map(|a| parse_expr(line, a))
+            .collect::<Result<Vec<Expr>, AsmError>>()?;
+        if values.is_empty() {
+            return Err(AsmError::new(line, "data needs at least one value"));
+        }
+        return Ok(Item::Data(values));
+    }
+
+    let op = lookup(mnemonic, specs)
+        .ok_or_else(|| AsmError::new(line, format!("unknown mnemonic `{}`", mnemonic)))?;
+    let operands = split_args(rest).into_iter()
+        .map(|a| parse_operand(line, a))
+        .collect::<Result<Vec<Operand>, AsmError>>()?;
+    let modes: Vec<Mode> = operands.iter().map(|o| o.mode).collect();
+    let built = match op {
+        Op::Ext(spec, _) => spec.op(&modes),
+        _ => Op::new(op.code(), &modes),
+    };
+    let op = match built {
+        Some(op) => op,
+        None => {
+            let msg = format!("`{}` takes {} operands, found {}", mnemonic, op.size() - 1, operands.len());
+            return Err(AsmError::new(line, msg));
+        },
+    };
+    if !op.is_valid() {
+        return Err(AsmError::new(line, format!("destination of `{}` can't be immediate", mnemonic)));
+    }
+    Ok(Item::Instr(op, operands))
+}
+
+fn resolve(line: usize, e: &Expr, labels: &HashMap<String, usize>) -> Result<isize, AsmError> {
+    match e {
+        Expr::Num(x) => Ok(*x),
+        Expr::Label(name, offset) => match labels.get(name) {
+            Some(addr) => Ok(*addr as isize + offset),
+            None => Err(AsmError::new(line, format!("undefined label `{}`", name))),
+        },
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<isize>, AsmError> {
+    assemble_with(source, &[])
+}
+
+// also accepts the mnemonics of extension instructions
+pub fn assemble_with(source: &str, specs: &[Spec]) -> Result<Vec<isize>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut addr = 0;
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut text = match text.find(';') {
+            Some(c) => &text[..c],
+            None => text,
+        }.trim();
+
+        while let Some(c) = text.find(':') {
+            let label = text[..c].trim();
+            if let Expr::Num(_) = parse_expr(line, label)? {
+                return Err(AsmError::new(line, format!("invalid label `{}`", label)));
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(AsmError::new(line, format!("duplicate label `{}`", label)));
+            }
+            text = text[c + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let item = parse_item(line, text, specs)?;
+        addr += match &item {
+            Item::Instr(_, operands) => 1 + operands.len(),
+            Item::Data(values) => values.len(),
+        };
+        items.push((line, item));
+    }
+
+    let mut prog = Vec::with_capacity(addr);
+    for (line, item) in items {
+        match item {
+            Item::Instr(op, operands) => {
+                prog.push(op.encode());
+                for o in operands {
+                    prog.push(resolve(line, &o.value, &labels)?);
+                }
+            },
+            Item::Data(values) => {
+                for v in values {
+                    prog.push(resolve(line, &v, &labels)?);
+                }
+            },
+        }
+    }
+    Ok(prog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disasm;
+    use crate::intcode::{Status, VM};
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            ; double the input
+                    read [x]
+                    mul [x], 2, [x]
+                    write [x]
+                    halt
+            x:      data 0
+        ";
+        let prog = assemble(source).unwrap();
+        assert_eq!(prog, vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]);
+
+        let mut vm = VM::new(&prog);
+        vm.push_input(21);
+        assert_eq!(vm.run(), Ok(Status::Output(42)));
+    }
+
+    #[test]
+    fn modes() {
+        let prog = assemble("
+            rebase 10
+            add [rb-1], 5, [rb+3]
+            jz [rb], end
+            equal [end+1], end, [rb + 2]
+            end: halt
+        ").unwrap();
+        assert_eq!(prog, vec![
+            109, 10,
+            21201, -1, 5, 3,
+            1206, 0, 13,
+            21008, 14, 13, 2,
+            99,
+        ]);
+        assert_eq!(assemble("add [rb - 1], [rb -2], [rb+ 3]").unwrap(), vec![22201, -1, -2, 3]);
+    }
+
+    #[test]
+    fn errors() {
+        let err = |s| assemble(s).unwrap_err();
+        assert_eq!(err("halt\nfoo 1"), AsmError::new(2, "unknown mnemonic `foo`"));
+        assert_eq!(err("add 1, 2"), AsmError::new(1, "`add` takes 3 operands, found 2"));
+        assert_eq!(err("\n\nadd 1, 2, 3"), AsmError::new(3, "destination of `add` can't be immediate"));
+        assert_eq!(err("jnz 1, nowhere"), AsmError::new(1, "undefined label `nowhere`"));
+        assert_eq!(err("a: halt\na: halt"), AsmError::new(2, "duplicate label `a`"));
+        assert_eq!(err("write [1x]"), AsmError::new(1, "invalid operand `1x`"));
+        assert_eq!(err("write [rb - x]"), AsmError::new(1, "invalid offset in `-x`"));
+        assert_eq!(err("data"), AsmError::new(1, "data needs at least one value"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let prog = vec![
+            3, 21,
+            1008, 21, 8, 20,
+            1005, 20, 22,
+            107, 8, 21, 20,
+            1006, 20, 31,
+            1106, 0, 36,
+            98, 0, 0,
+            1002, 21, 125, 20,
+            4, 20,
+            1105, 1, 46,
+            104, 999,
+            1105, 1, 46,
+            1101, 1000, 1, 20,
+            4, 20,
+            1105, 1, 46,
+            98,
+            99,
+            21201, -1, 5, 3,
+        ];
+        let source = disasm::source(&disasm::disassemble_reachable(&prog));
+        assert_eq!(assemble(&source).unwrap(), prog);
+
+        let source = disasm::source(&disasm::disassemble(&prog));
+        assert_eq!(assemble(&source).unwrap(), prog);
+    }
+}
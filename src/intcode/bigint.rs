@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use super::word::{Overflow, Word};
+
+// sign and magnitude, the magnitude in little endian base 2^32 limbs with no
+// trailing zeros; zero is never negative, so derived Eq and Hash agree with
+// the numeric value
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParseBigIntError;
+
+impl fmt::Display for ParseBigIntError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid digit in integer")
+    }
+}
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0;
+    for (i, x) in a.iter().enumerate() {
+        let sum = *x as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+// a - b, where a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, x) in a.iter().enumerate() {
+        let diff = *x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = if diff < 0 { 1 } else { 0 };
+        out.push(diff.rem_euclid(1 << 32) as u32);
+    }
+    trim(out)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, y) in b.iter().enumerate() {
+            let t = *x as u64 * *y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(out)
+}
+
+// mag * m + a, in place
+fn mul_add_small(mag: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = a as u64;
+    for x in mag.iter_mut() {
+        let t = *x as u64 * m as u64 + carry;
+        *x = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+// mag / d in place, returning the remainder
+fn div_small(mag: &mut Vec<u32>, d: u32) -> u32 {
+    let mut rem = 0u64;
+    for x in mag.iter_mut().rev() {
+        let cur = (rem << 32) | *x as u64;
+        *x = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    let trimmed = trim(std::mem::take(mag));
+    *mag = trimmed;
+    rem as u32
+}
+
+impl BigInt {
+    fn new(neg: bool, mag: Vec<u32>) -> Self {
+        let mag = trim(mag);
+        Self { neg: neg && !mag.is_empty(), mag }
+    }
+
+    pub fn from_i128(x: i128) -> Self {
+        let u = x.unsigned_abs();
+        Self::new(x < 0, (0..4).map(|i| (u >> (32 * i)) as u32).collect())
+    }
+
+    pub fn to_i128(&self) -> Option<i128> {
+        if self.mag.len() > 4 {
+            return None;
+        }
+        let u = self.mag.iter().rev().fold(0u128, |acc, x| (acc << 32) | *x as u128);
+        if self.neg {
+            if u <= i128::MAX as u128 + 1 {
+                Some((u as i128).wrapping_neg())
+            } else {
+                None
+            }
+        } else if u <= i128::MAX as u128 {
+            Some(u as i128)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(x: i128) -> Self {
+        Self::from_i128(x)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mag.is_empty() {
+            return write!(f, "0");
+        }
+        // nine decimal digits at a time, least significant first
+        let mut mag = self.mag.clone();
+        let mut chunks = Vec::new();
+        while !mag.is_empty() {
+            chunks.push(div_small(&mut mag, 1_000_000_000));
+        }
+        if self.neg {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for c in chunks.iter().rev() {
+            write!(f, "{:09}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (neg, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+        let mut mag = Vec::new();
+        for c in digits.bytes() {
+            mul_add_small(&mut mag, 10, (c - b'0') as u32);
+        }
+        Ok(Self::new(neg, mag))
+    }
+}
+
+impl Word for BigInt {
+    fn from_isize(x: isize) -> Self {
+        Self::from_i128(x as i128)
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        use std::convert::TryFrom;
+        self.to_i128().and_then(|x| isize::try_from(x).ok())
+    }
+
+    // never overflows, so the policy doesn't matter
+    fn add(&self, other: &Self, _: Overflow) -> Option<Self> {
+        if self.neg == other.neg {
+            return Some(Self::new(self.neg, add_mag(&self.mag, &other.mag)));
+        }
+        Some(match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => Self::new(other.neg, sub_mag(&other.mag, &self.mag)),
+            _ => Self::new(self.neg, sub_mag(&self.mag, &other.mag)),
+        })
+    }
+
+    fn mul(&self, other: &Self, _: Overflow) -> Option<Self> {
+        Some(Self::new(self.neg != other.neg, mul_mag(&self.mag, &other.mag)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!(a.add(&b, Overflow::Checked).unwrap().to_string(), "-864197532086419753208641975320");
+        assert_eq!(a.mul(&b, Overflow::Checked).unwrap().to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900");
+        assert_eq!(a.add(&big("-123456789012345678901234567890"), Overflow::Checked), Some(BigInt::default()));
+        assert_eq!(big("-0"), BigInt::default());
+        assert_eq!(big("1000000000").to_string(), "1000000000");
+    }
+
+    #[test]
+    fn conversions() {
+        for x in &[0, 1, -1, i128::MAX, i128::MIN, 1 << 64, -(1 << 96)] {
+            assert_eq!(BigInt::from_i128(*x).to_i128(), Some(*x));
+            assert_eq!(BigInt::from_i128(*x).to_string(), x.to_string());
+        }
+        assert_eq!(big("170141183460469231731687303715884105728").to_i128(), None);
+        assert_eq!(big("-9").to_isize(), Some(-9));
+        assert!("12a".parse::<BigInt>().is_err());
+        assert!("-".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        let mut xs = [big("5"), big("-70000000000000000000"), big("0"), big("70000000000000000000"), big("-5")];
+        xs.sort();
+        let s: Vec<String> = xs.iter().map(|x| x.to_string()).collect();
+        assert_eq!(s, vec!["-70000000000000000000", "-5", "0", "5", "70000000000000000000"]);
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disasm::{self, Line};
+use super::{Mode, Op};
+
+// how control leaves a basic block
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Halt,
+    // an unconditional jump, or falling through into the next block
+    Goto(usize),
+    Branch { taken: usize, fall: usize },
+    // the target is read from memory; fall is set for conditional jumps
+    Indirect { fall: Option<usize> },
+    // an unconditional jump to a negative address, which always fails
+    Fault,
+    // compiled code calls by storing the return address, usually on the
+    // relative base stack, and jumping to the function
+    Call { target: usize, ret: usize },
+    // and returns by jumping through a relative parameter
+    Return,
+}
+
+impl Exit {
+    // successors that are known statically, within the same function
+    pub fn targets(&self) -> Vec<usize> {
+        match *self {
+            Exit::Goto(addr) => vec![addr],
+            Exit::Branch { taken, fall } => vec![taken, fall],
+            Exit::Indirect { fall } => fall.into_iter().collect(),
+            Exit::Call { ret, .. } => vec![ret],
+            Exit::Halt | Exit::Fault | Exit::Return => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    // one past the last word of the last instruction
+    pub end: usize,
+    pub lines: Vec<Line>,
+    pub exit: Exit,
+}
+
+// a write whose destination is known statically and lands on a word of
+// reachable code, either an op code or a parameter
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CodeWrite {
+    pub pc: usize,
+    pub addr: usize,
+}
+
+// basic blocks of the code reachable from address 0, keyed by their first
+// address. Only immediate jump targets and calls are followed, so code
+// reached through other indirect jumps is missing; relative destinations
+// can't be checked for self-modification either.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    // entry points: 0 and every call target
+    pub functions: BTreeSet<usize>,
+    // addresses of jumps whose target is read from memory, returns aside
+    pub indirect: Vec<usize>,
+    pub self_modifying: Vec<CodeWrite>,
+}
+
+// the value stored by an instruction whose inputs are both immediate
+fn constant(prog: &[isize], addr: usize, op: Op) -> Option<isize> {
+    let (a, b) = (prog[addr + 1], prog[addr + 2]);
+    match op {
+        Op::Add(Mode::Imm, Mode::Imm, _) => a.checked_add(b),
+        Op::Mul(Mode::Imm, Mode::Imm, _) => a.checked_mul(b),
+        _ => None,
+    }
+}
+
+// stores_next says whether the address after the jump was stored as a
+// constant on the way to it, which makes an unconditional jump a call
+fn jump_exit(prog: &[isize], addr: usize, op: Op, stores_next: bool) -> Exit {
+    let (m, a_mode, dst_mode) = match op {
+        Op::Jump(m, a_mode, dst_mode) => (m, a_mode, dst_mode),
+        _ => panic!("not a jump"),
+    };
+    let (a, dst) = (prog[addr + 1], prog[addr + 2]);
+    let next = addr + op.size();
+    // Some(true) if the jump is always taken, Some(false) if never
+    let cond = if a_mode == Mode::Imm { Some((a != 0) == m) } else { None };
+    match (cond, dst_mode) {
+        (Some(false), _) => Exit::Goto(next),
+        (Some(true), Mode::Imm) if dst >= 0 && stores_next => Exit::Call { target: dst as usize, ret: next },
+        (Some(true), Mode::Imm) if dst >= 0 => Exit::Goto(dst as usize),
+        (Some(true), Mode::Imm) => Exit::Fault,
+        (None, Mode::Imm) if dst >= 0 => Exit::Branch { taken: dst as usize, fall: next },
+        (None, Mode::Imm) => Exit::Goto(next),
+        (Some(true), Mode::Rel) => Exit::Return,
+        (Some(true), _) => Exit::Indirect { fall: None },
+        (None, _) => Exit::Indirect { fall: Some(next) },
+    }
+}
+
+impl Cfg {
+    pub fn new(prog: &[isize]) -> Self {
+        // follows straight line code from each address in the queue, like
+        // disasm::reachable but also continuing after calls. Blocks start at
+        // the entry point, at jump targets and after every jump or halt.
+        let mut ops: BTreeMap<usize, Op> = BTreeMap::new();
+        let mut exits = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut cfg = Cfg::default();
+        leaders.insert(0);
+        cfg.functions.insert(0);
+        let mut queue = vec![0];
+        while let Some(mut addr) = queue.pop() {
+            let mut stored = Vec::new();
+            while !ops.contains_key(&addr) && addr < prog.len() {
+                let op = match disasm::decode(prog, addr) {
+                    Some(op) => op,
+                    None => break,
+                };
+                ops.insert(addr, op);
+                let next = addr + op.size();
+                match op {
+                    Op::Halt => {
+                        leaders.insert(next);
+                        break;
+                    },
+                    Op::Jump(_, _, _) => {
+                        let exit = jump_exit(prog, addr, op, stored.contains(&(next as isize)));
+                        if let Exit::Call { target, .. } = exit {
+                            cfg.functions.insert(target);
+                            leaders.insert(target);
+                            queue.push(target);
+                        }
+                        leaders.extend(exit.targets());
+                        queue.extend(exit.targets());
+                        leaders.insert(next);
+                        exits.insert(addr, exit);
+                        break;
+                    },
+                    _ => stored.extend(constant(prog, addr, op)),
+                }
+                addr = next;
+            }
+        }
+        leaders.retain(|addr| ops.contains_key(addr));
+        cfg.functions.retain(|addr| ops.contains_key(addr));
+
+        for start in &leaders {
+            let mut lines = Vec::new();
+            let mut addr = *start;
+            let exit = loop {
+                let op = ops[&addr];
+                lines.push(disasm::line(prog, addr, Some(op)));
+                let next = addr + op.size();
+                match op {
+                    Op::Halt => break Exit::Halt,
+                    Op::Jump(_, _, _) => break exits[&addr],
+                    _ if leaders.contains(&next) || !ops.contains_key(&next) => break Exit::Goto(next),
+                    _ => addr = next,
+                }
+            };
+            if let Exit::Indirect { .. } = exit {
+                cfg.indirect.push(addr);
+            }
+            let end = addr + ops[&addr].size();
+            cfg.blocks.insert(*start, Block { start: *start, end, lines, exit });
+        }
+
+        let words: BTreeSet<usize> = ops.iter()
+            .flat_map(|(addr, op)| *addr..addr + op.size())
+            .collect();
+        for (pc, op) in &ops {
+            if op.dst() != Some(Mode::Ptr) {
+                continue;
+            }
+            // the destination is always the last parameter
+            let dst = prog[pc + op.size() - 1];
+            if dst >= 0 && words.contains(&(dst as usize)) {
+                cfg.self_modifying.push(CodeWrite { pc: *pc, addr: dst as usize });
+            }
+        }
+        cfg
+    }
+
+    // the block containing an instruction address
+    pub fn block_at(&self, addr: usize) -> Option<&Block> {
+        self.blocks.range(..=addr)
+            .rev()
+            .map(|(_, b)| b)
+            .find(|b| b.lines.iter().any(|l| l.addr == addr))
+    }
+
+    pub fn successors(&self, start: usize) -> Vec<usize> {
+        self.blocks.get(&start).map_or(Vec::new(), |b| b.exit.targets())
+    }
+
+    pub fn predecessors(&self, start: usize) -> Vec<usize> {
+        self.blocks.values()
+            .filter(|b| b.exit.targets().contains(&start))
+            .map(|b| b.start)
+            .collect()
+    }
+
+    // Graphviz source; blocks that write into code are red, indirect jumps
+    // lead to a single `?` node and targets outside any block are plain text
+    pub fn dot(&self) -> String {
+        let writers: BTreeSet<usize> = self.self_modifying.iter().map(|w| w.pc).collect();
+        let mut s = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for b in self.blocks.values() {
+            let label: String = b.lines.iter()
+                .map(|l| format!("{:04}  {}\\l", l.addr, l.text()))
+                .collect();
+            let color = if b.lines.iter().any(|l| writers.contains(&l.addr)) { ", color=red" } else { "" };
+            writeln!(s, "    b{} [label=\"{}\"{}];", b.start, label, color).unwrap();
+        }
+
+        let mut missing = BTreeSet::new();
+        let mut node = |addr: usize| {
+            if self.blocks.contains_key(&addr) {
+                format!("b{}", addr)
+            } else {
+                missing.insert(addr);
+                format!("d{}", addr)
+            }
+        };
+        let mut edges = Vec::new();
+        for b in self.blocks.values() {
+            let from = format!("b{}", b.start);
+            match b.exit {
+                Exit::Goto(to) => edges.push(format!("{} -> {};", from, node(to))),
+                Exit::Branch { taken, fall } => {
+                    edges.push(format!("{} -> {} [label=\"taken\"];", from, node(taken)));
+                    edges.push(format!("{} -> {} [label=\"else\"];", from, node(fall)));
+                },
+                Exit::Indirect { fall } => {
+                    edges.push(format!("{} -> indirect [style=dashed];", from));
+                    if let Some(fall) = fall {
+                        edges.push(format!("{} -> {} [label=\"else\"];", from, node(fall)));
+                    }
+                },
+                Exit::Call { target, ret } => {
+                    edges.push(format!("{} -> {} [style=dotted, label=\"call\"];", from, node(target)));
+                    edges.push(format!("{} -> {};", from, node(ret)));
+                },
+                Exit::Halt | Exit::Fault | Exit::Return => (),
+            }
+        }
+        if !self.indirect.is_empty() {
+            s.push_str("    indirect [label=\"?\", shape=diamond];\n");
+        }
+        for addr in missing {
+            writeln!(s, "    d{} [label=\"{:04} data\", shape=plaintext];", addr, addr).unwrap();
+        }
+        for e in edges {
+            writeln!(s, "    {}", e).unwrap();
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // a loop, self-modifying code and a jump through a pointer, unlike
+    // the straight countdown in testing
+    fn pointer_jump() -> Vec<isize> {
+        assemble("
+                    read [n]
+            loop:   jz [n], done
+                    add [n], -1, [n]
+                    jnz 1, loop
+            done:   write [n]
+                    add 99, 0, [fin]
+                    jnz [n], [ret]
+            fin:    halt
+            n:      data 0
+            ret:    data 21
+        ").unwrap()
+    }
+
+    #[test]
+    fn blocks() {
+        let cfg = Cfg::new(&pointer_jump());
+        let exits: Vec<(usize, usize, Exit)> = cfg.blocks.values().map(|b| (b.start, b.end, b.exit)).collect();
+        assert_eq!(exits, vec![
+            (0, 2, Exit::Goto(2)),
+            (2, 5, Exit::Branch { taken: 12, fall: 5 }),
+            (5, 12, Exit::Goto(2)),
+            (12, 21, Exit::Indirect { fall: Some(21) }),
+            (21, 22, Exit::Halt),
+        ]);
+        assert_eq!(cfg.indirect, vec![18]);
+        assert_eq!(cfg.self_modifying, vec![CodeWrite { pc: 14, addr: 21 }]);
+
+        assert_eq!(cfg.predecessors(2), vec![0, 5]);
+        assert_eq!(cfg.successors(2), vec![12, 5]);
+        assert_eq!(cfg.block_at(9).map(|b| b.start), Some(5));
+        assert_eq!(cfg.block_at(10), None);
+    }
+
+    // calls double(5) with the return address and argument on the stack
+    fn call() -> Vec<isize> {
+        assemble("
+                    rebase stack
+                    add ret, 0, [rb]
+                    add 5, 0, [rb+1]
+                    jnz 1, double
+            ret:    write [rb+1]
+                    halt
+            double: rebase 2
+                    mul [rb-1], 2, [rb-1]
+                    rebase -2
+                    jz 0, [rb]
+            stack:  data 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn calls() {
+        let prog = call();
+        let mut vm = crate::intcode::VM::new(&prog);
+        assert_eq!(vm.run(), Ok(crate::intcode::Status::Output(10)));
+
+        let cfg = Cfg::new(&prog);
+        let exits: Vec<(usize, usize, Exit)> = cfg.blocks.values().map(|b| (b.start, b.end, b.exit)).collect();
+        assert_eq!(exits, vec![
+            (0, 13, Exit::Call { target: 16, ret: 13 }),
+            (13, 16, Exit::Halt),
+            (16, 27, Exit::Return),
+        ]);
+        assert_eq!(cfg.functions.iter().copied().collect::<Vec<_>>(), vec![0, 16]);
+        assert!(cfg.indirect.is_empty());
+        assert!(cfg.dot().contains("    b0 -> b16 [style=dotted, label=\"call\"];\n    b0 -> b13;\n"));
+    }
+
+    #[test]
+    fn jumps() {
+        // never taken, always taken to a negative address, into data
+        let cfg = Cfg::new(&[1106, 1, 7, 1105, 1, -1]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Goto(3));
+        assert_eq!(cfg.blocks[&3].exit, Exit::Fault);
+
+        let cfg = Cfg::new(&[1005, 9, 6, 1, 0, 0, 0, 99, 0, 0]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Branch { taken: 6, fall: 3 });
+        assert_eq!(cfg.blocks[&3].exit, Exit::Halt);
+        assert!(!cfg.blocks.contains_key(&6));
+        assert!(cfg.dot().contains("    d6 [label=\"0006 data\", shape=plaintext];\n"));
+    }
+
+    #[test]
+    fn dot() {
+        let dot = Cfg::new(&pointer_jump()).dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b12 [label=\"0012  write [22]\\l0014  add 99, 0, [21]\\l0018  jnz [22], [23]\\l\", color=red];\n"));
+        assert!(dot.contains("    b2 -> b12 [label=\"taken\"];\n"));
+        assert!(dot.contains("    b5 -> b2;\n"));
+        assert!(dot.contains("    b12 -> indirect [style=dashed];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn puzzle_input() {
+        let prog = crate::intcode::parse(&crate::load("02.txt")).unwrap();
+        let cfg = Cfg::new(&prog);
+        // the first instruction overwrites its own destination parameter
+        assert_eq!(cfg.self_modifying[0], CodeWrite { pc: 0, addr: 3 });
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Halt);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use super::disasm::{self, Line};
+use super::watch::{Access, Hit};
+use super::{Op, Status, VM, VmError};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Step,
+    Breakpoint(usize),
+    Opcode(usize, Op),
+    Output(isize),
+    Watch(Hit),
+    NeedsInput,
+    Halted,
+    Error(VmError),
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    pub vm: VM,
+    pub output: Vec<isize>,
+    breakpoints: BTreeSet<usize>,
+    break_ops: BTreeSet<isize>,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            output: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            break_ops: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &usize> {
+        self.breakpoints.iter()
+    }
+
+    // stop before executing any instruction with this opcode
+    pub fn break_on_op(&mut self, code: isize) {
+        self.break_ops.insert(code);
+    }
+
+    pub fn clear_op(&mut self, code: isize) -> bool {
+        self.break_ops.remove(&code)
+    }
+
+    pub fn current(&self) -> Line {
+        self.line_at(self.vm.pc)
+    }
+
+    // built in instructions, then any the VM has registered
+    fn decode(&self, x: isize) -> Option<Op> {
+        Op::from(x).or_else(|| self.vm.extensions().decode(x))
+    }
+
+    fn line_at(&self, addr: usize) -> Line {
+        match self.decode(self.vm.mem[addr]) {
+            Some(op) => Line {
+                addr,
+                words: (addr..addr + op.size()).map(|a| self.vm.mem[a]).collect(),
+                op: Some(op),
+            },
+            None => Line { addr, words: vec![self.vm.mem[addr]], op: None },
+        }
+    }
+
+    pub fn step(&mut self) -> Stop {
+        match self.vm.step() {
+            Ok(Status::Running) => Stop::Step,
+            Ok(Status::Output(x)) => {
+                self.output.push(x);
+                Stop::Output(x)
+            },
+            Ok(Status::Watch(hit)) => Stop::Watch(hit),
+            Ok(Status::NeedsInput) => Stop::NeedsInput,
+            Ok(Status::Halted) => Stop::Halted,
+            Err(e) => Stop::Error(e),
+        }
+    }
+
+    // runs until a breakpoint, watchpoint, watched opcode, input, halt or
+    // error; the instruction under the pc always executes so a stopped run
+    // can resume
+    pub fn cont(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Step | Stop::Output(_) => (),
+                stop => return stop,
+            }
+            if let Some(stop) = self.check() {
+                return stop;
+            }
+        }
+    }
+
+    // a jump is run until execution returns to the instruction after it,
+    // anything else is a single step
+    pub fn step_over(&mut self) -> Stop {
+        let line = self.current();
+        match line.op {
+            Some(Op::Jump(_, _, _)) => {
+                let next = line.addr + line.words.len();
+                loop {
+                    match self.step() {
+                        Stop::Step | Stop::Output(_) => (),
+                        stop => return stop,
+                    }
+                    if self.vm.pc == next {
+                        return Stop::Step;
+                    }
+                    if let Some(stop) = self.check() {
+                        return stop;
+                    }
+                }
+            },
+            _ => self.step(),
+        }
+    }
+
+    fn check(&self) -> Option<Stop> {
+        let pc = self.vm.pc;
+        if self.breakpoints.contains(&pc) {
+            return Some(Stop::Breakpoint(pc));
+        }
+        match self.decode(self.vm.mem[pc]) {
+            Some(op) if self.break_ops.contains(&op.code()) => Some(Stop::Opcode(pc, op)),
+            _ => None,
+        }
+    }
+
+    pub fn registers(&self) -> String {
+        format!("pc={:04} rb={} halt={}", self.vm.pc, self.vm.base, self.vm.halt)
+    }
+
+    // eight words per row, each row prefixed with its address
+    pub fn dump(&self, addr: usize, count: usize) -> String {
+        let mut s = String::new();
+        let end = addr.saturating_add(count);
+        for row in (addr..end).step_by(8) {
+            let words: Vec<String> = (row..usize::min(row.saturating_add(8), end))
+                .map(|a| self.vm.mem[a].to_string())
+                .collect();
+            s += &format!("{:04}: {}\n", row, words.join(" "));
+        }
+        s
+    }
+
+    pub fn list(&self, addr: usize, count: usize) -> String {
+        let mut lines = Vec::new();
+        let mut a = addr;
+        for _ in 0..count {
+            let line = self.line_at(a);
+            a = a.saturating_add(line.words.len());
+            lines.push(line);
+        }
+        disasm::listing(&lines)
+    }
+
+    // runs one REPL command and returns what to print, or None to quit
+    pub fn command(&mut self, input: &str) -> Option<String> {
+        let mut words = input.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Some(String::new()),
+        };
+        let args: Vec<isize> = match words.map(isize::from_str).collect() {
+            Ok(args) => args,
+            Err(_) => return Some("arguments must be integers\n".to_string()),
+        };
+        // only inputs can be negative, everything else is an address or count
+        let addrs: Vec<usize> = match cmd {
+            "i" | "input" => Vec::new(),
+            _ => match args.iter().map(|x| usize::try_from(*x)).collect() {
+                Ok(addrs) => addrs,
+                Err(_) => return Some("arguments must not be negative\n".to_string()),
+            },
+        };
+        let arg = |i: usize, default: usize| addrs.get(i).copied().unwrap_or(default);
+
+        let printed = self.output.len();
+        let text = match cmd {
+            "s" | "step" => {
+                let mut stop = Stop::Step;
+                for _ in 0..arg(0, 1) {
+                    stop = self.step();
+                    if !matches!(stop, Stop::Step | Stop::Output(_)) {
+                        break;
+                    }
+                }
+                self.report(stop)
+            },
+            "n" | "next" => {
+                let stop = self.step_over();
+                self.report(stop)
+            },
+            "c" | "continue" => {
+                let stop = self.cont();
+                self.report(stop)
+            },
+            "b" | "break" => match addrs.first() {
+                Some(addr) => {
+                    self.add_breakpoint(*addr);
+                    format!("breakpoint at {:04}\n", addr)
+                },
+                None => self.breakpoints().map(|a| format!("breakpoint at {:04}\n", a)).collect(),
+            },
+            "d" | "delete" => match addrs.first() {
+                Some(addr) if self.remove_breakpoint(*addr) => format!("deleted {:04}\n", addr),
+                _ => "no such breakpoint\n".to_string(),
+            },
+            "o" | "op" => match args.first() {
+                Some(code) if Op::arity(*code).is_some() || self.vm.extensions().get(*code).is_some() => {
+                    self.break_on_op(*code);
+                    format!("stopping on opcode {}\n", code)
+                },
+                _ => "unknown opcode\n".to_string(),
+            },
+            "w" | "watch" | "rw" => match addrs.first() {
+                Some(addr) => {
+                    let end = arg(1, *addr);
+                    let access = if cmd == "rw" { Access::Any } else { Access::Write };
+                    self.vm.watch(*addr..=end, access);
+                    format!("watching {:04}..={:04}\n", addr, end)
+                },
+                None => "watch needs an address\n".to_string(),
+            },
+            "uw" | "unwatch" => match addrs.first() {
+                Some(addr) if self.vm.unwatch(*addr) => format!("unwatched {:04}\n", addr),
+                _ => "no such watchpoint\n".to_string(),
+            },
+            "rec" | "record" => {
+                self.vm.record();
+                "recording\n".to_string()
+            },
+            "back" => {
+                let mut undone = 0;
+                while undone < arg(0, 1) && self.vm.step_back().is_some() {
+                    undone += 1;
+                }
+                format!("stepped back {}\n{}\n", undone, self.current())
+            },
+            "r" | "regs" => format!("{}\n", self.registers()),
+            "x" | "mem" => self.dump(arg(0, self.vm.pc), arg(1, 8)),
+            "l" | "list" => self.list(arg(0, self.vm.pc), arg(1, 10)),
+            "i" | "input" => {
+                for x in &args {
+                    self.vm.push_input(*x);
+                }
+                format!("queued {} input(s)\n", args.len())
+            },
+            "q" | "quit" => return None,
+            "h" | "help" => HELP.to_string(),
+            _ => format!("unknown command `{}`, try `help`\n", cmd),
+        };
+
+        let output: String = self.output[printed..].iter()
+            .map(|x| format!("output: {}\n", x))
+            .collect();
+        Some(output + &text)
+    }
+
+    fn report(&self, stop: Stop) -> String {
+        let why = match stop {
+            Stop::Step | Stop::Output(_) => String::new(),
+            Stop::Breakpoint(addr) => format!("breakpoint at {:04}\n", addr),
+            Stop::Opcode(addr, op) => format!("{} at {:04}\n", op.mnemonic(), addr),
+            Stop::Watch(hit) => format!("watch: {}\n", hit),
+            Stop::NeedsInput => "waiting for input\n".to_string(),
+            Stop::Halted => return "halted\n".to_string(),
+            Stop::Error(e) => format!("error: {}\n", e),
+        };
+        format!("{}{}\n", why, self.current())
+    }
+}
+
+const HELP: &str = "\
+s, step [n]         execute n instructions
+n, next             step over a jump
+c, continue         run to the next breakpoint
+b, break [addr]     set or list breakpoints
+d, delete addr      remove a breakpoint
+o, op code          stop before any instruction with this opcode
+w, watch a [b]      stop after writes to addresses a..=b
+rw a [b]            stop after reads or writes to addresses a..=b
+uw, unwatch addr    remove watchpoints covering an address
+rec, record         record from here on so steps can be undone
+back [n]            undo n recorded instructions
+r, regs             show pc, relative base and halt flag
+x, mem [addr] [n]   dump n words of memory
+l, list [addr] [n]  disassemble n instructions
+i, input x...       queue input values
+q, quit
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::testing;
+
+    fn countdown() -> VM {
+        VM::new(&testing::countdown())
+    }
+
+    #[test]
+    fn extensions() {
+        let vm = testing::with_neg("add 1, 2, [x]\nneg [x], [x]\nhalt\nx: data 0");
+        let mut dbg = Debugger::new(vm);
+        assert_eq!(dbg.command("l 4 1").unwrap(), "0004  10 8 8                    neg [8], [8]\n");
+        assert_eq!(dbg.command("o 10").unwrap(), "stopping on opcode 10\n");
+        assert_eq!(dbg.cont(), Stop::Opcode(4, testing::NEG.decode(10).unwrap()));
+        assert_eq!(dbg.cont(), Stop::Halted);
+        assert_eq!(dbg.vm.mem[8], -3);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = Debugger::new(countdown());
+        dbg.add_breakpoint(2);
+        assert_eq!(dbg.cont(), Stop::NeedsInput);
+
+        dbg.vm.push_input(3);
+        assert_eq!(dbg.cont(), Stop::Breakpoint(2));
+        assert_eq!(dbg.cont(), Stop::Breakpoint(2));
+        assert_eq!(dbg.output, vec![3]);
+
+        assert!(dbg.remove_breakpoint(2));
+        assert_eq!(dbg.cont(), Stop::Halted);
+        assert_eq!(dbg.output, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn opcode_and_step_over() {
+        let mut dbg = Debugger::new(countdown());
+        dbg.vm.push_input(3);
+        dbg.break_on_op(5);
+        assert_eq!(dbg.cont(), Stop::Opcode(8, Op::from(1005).unwrap()));
+        assert_eq!(dbg.current().text(), "jnz [12], 2");
+
+        // steps over the loop: back at the jump after another iteration
+        dbg.clear_op(5);
+        assert_eq!(dbg.step_over(), Stop::Step);
+        assert_eq!(dbg.vm.pc, 11);
+        assert_eq!(dbg.output, vec![3, 2, 1]);
+        assert_eq!(dbg.step(), Stop::Halted);
+    }
+
+    #[test]
+    fn repl() {
+        let mut dbg = Debugger::new(countdown());
+        assert_eq!(dbg.command("regs").unwrap(), "pc=0000 rb=0 halt=false\n");
+        assert_eq!(dbg.command("x 0 10").unwrap(), "0000: 3 12 4 12 1001 12 -1 12\n0008: 1005 12\n");
+        assert_eq!(dbg.command("l 0 2").unwrap(), "\
+0000  3 12                      read [12]
+0002  4 12                      write [12]
+");
+        assert_eq!(dbg.command("b 8").unwrap(), "breakpoint at 0008\n");
+        assert_eq!(dbg.command("c").unwrap(), "waiting for input\n0000  3 12                      read [12]\n");
+        assert_eq!(dbg.command("i 2").unwrap(), "queued 1 input(s)\n");
+        assert_eq!(dbg.command("c").unwrap(), "\
+output: 2
+breakpoint at 0008
+0008  1005 12 2                 jnz [12], 2
+");
+        assert_eq!(dbg.command("d 8").unwrap(), "deleted 0008\n");
+        assert_eq!(dbg.command("w 12").unwrap(), "watching 0012..=0012\n");
+        assert_eq!(dbg.command("c").unwrap(), "\
+output: 1
+watch: 0004 add wrote [12] 1 -> 0
+0008  1005 12 2                 jnz [12], 2
+");
+        assert_eq!(dbg.command("uw 12").unwrap(), "unwatched 0012\n");
+        assert_eq!(dbg.command("back").unwrap(), "stepped back 0\n0008  1005 12 2                 jnz [12], 2\n");
+        assert_eq!(dbg.command("rec").unwrap(), "recording\n");
+        assert_eq!(dbg.command("s").unwrap(), "0011  99                        halt\n");
+        assert_eq!(dbg.command("back").unwrap(), "stepped back 1\n0008  1005 12 2                 jnz [12], 2\n");
+        assert_eq!(dbg.command("c").unwrap(), "halted\n");
+        assert!(dbg.command("frobnicate").unwrap().starts_with("unknown command"));
+        assert_eq!(dbg.command("q"), None);
+    }
+
+    #[test]
+    fn negative_arguments() {
+        let mut dbg = Debugger::new(countdown());
+        for cmd in &["x -1", "l -1", "x 0 -8", "b -3", "d -3", "w -1", "uw -1", "s -1", "back -2"] {
+            assert_eq!(dbg.command(cmd).unwrap(), "arguments must not be negative\n", "{}", cmd);
+        }
+        assert_eq!(dbg.command("i -5").unwrap(), "queued 1 input(s)\n");
+        assert_eq!(dbg.command("x 12 1").unwrap(), "0012: 0\n");
+        assert_eq!(dbg.command(&format!("x {} 2", isize::MAX)).unwrap().lines().count(), 1);
+        assert_eq!(dbg.list(usize::MAX, 2).lines().count(), 2);
+    }
+}
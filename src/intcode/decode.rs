@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use super::{Op, VM, VmError};
+use super::word::Word;
+
+// decoded instructions by address, each kept with the word it was decoded
+// from; a fetch only uses an entry while memory still holds that word, so
+// self-modifying writes, restores and direct writes to `mem` all fall back to
+// decoding. Clones share entries until one of them decodes something new.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeCache<W = isize> {
+    ops: Arc<Vec<Option<(W, Op)>>>,
+}
+
+impl<W: Word> DecodeCache<W> {
+    // decodes every word of the program that is a valid instruction, so VMs
+    // sharing the cache never decode the unmodified image
+    pub fn new(program: &[W]) -> Self {
+        let ops = program.iter()
+            .map(|w| {
+                let op = Op::from(w.to_isize()?)?;
+                Some((w.clone(), op))
+            })
+            .collect();
+        Self { ops: Arc::new(ops) }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, addr: usize, word: &W) -> Option<Op> {
+        match self.ops.get(addr) {
+            Some(Some((w, op))) if w == word => Some(*op),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, addr: usize, word: W, op: Op) {
+        let ops = Arc::make_mut(&mut self.ops);
+        if ops.len() <= addr {
+            ops.resize(addr + 1, None);
+        }
+        ops[addr] = Some((word, op));
+    }
+
+    // number of addresses with an entry, valid or not
+    pub fn len(&self) -> usize {
+        self.ops.iter().filter(|e| e.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// addresses past this are decoded every time rather than growing the cache
+const MAX_CACHED: usize = 1 << 20;
+
+impl<W: Word> VM<W> {
+    // starts caching decoded instructions, beginning with the current memory;
+    // this decodes the whole image, so for many short runs of one program
+    // build a DecodeCache once and share it instead
+    pub fn predecode(&mut self) {
+        self.decoded = Some(DecodeCache::new(&self.mem.to_vec()));
+    }
+
+    // uses a cache built elsewhere, typically from the same program, so a
+    // brute force search over many VMs decodes the image once
+    pub fn share_decoded(&mut self, cache: &DecodeCache<W>) {
+        self.decoded = Some(cache.clone());
+    }
+
+    pub fn decode_cache(&self) -> Option<&DecodeCache<W>> {
+        self.decoded.as_ref()
+    }
+
+    pub fn clear_decoded(&mut self) {
+        self.decoded = None;
+    }
+
+    #[inline(always)]
+    pub(super) fn fetch(&mut self) -> Result<Op, VmError> {
+        let word = &self.mem[self.pc];
+        if let Some(op) = self.decoded.as_ref().and_then(|c| c.get(self.pc, word)) {
+            return Ok(op);
+        }
+        let op_code = word.to_isize().ok_or(VmError::OutOfRange { pc: self.pc })?;
+        let op = Op::from(op_code)
+            .or_else(|| self.extensions.decode(op_code))
+            .ok_or(VmError::InvalidOpcode { pc: self.pc, op_code })?;
+        if let Some(c) = &mut self.decoded {
+            if self.pc < MAX_CACHED {
+                c.insert(self.pc, word.clone(), op);
+            }
+        }
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::{Mode, Status};
+
+    // runs to completion feeding the given inputs, returning everything
+    // observable about the run
+    fn run(mut vm: VM, inputs: &[isize]) -> (Vec<isize>, Result<Status, VmError>, Vec<isize>, usize) {
+        let mut inputs = inputs.iter();
+        let mut outputs = Vec::new();
+        let end = loop {
+            match vm.run_for(100_000) {
+                Ok(Status::Output(x)) => outputs.push(x),
+                Ok(Status::NeedsInput) => match inputs.next() {
+                    Some(x) => vm.push_input(*x),
+                    None => break Ok(Status::NeedsInput),
+                },
+                end => break end,
+            }
+        };
+        (outputs, end, vm.mem.to_vec(), vm.pc)
+    }
+
+    fn differential(program: &[isize], inputs: &[isize]) {
+        let plain = run(VM::new(program), inputs);
+        let mut vm = VM::new(program);
+        vm.predecode();
+        assert_eq!(run(vm, inputs), plain, "predecoded run of {:?}", program);
+
+        let cache = DecodeCache::new(program);
+        let mut vm = VM::new(program);
+        vm.share_decoded(&cache);
+        assert_eq!(run(vm, inputs), plain, "shared cache run of {:?}", program);
+    }
+
+    // an add at `op` that the loop turns into a mul after the first pass
+    fn self_modifying() -> Vec<isize> {
+        assemble("
+            op:     add [x], [x], [x]
+                    write [x]
+                    add [n], -1, [n]
+                    add [op], 1, [op]
+                    jnz [n], op
+                    halt
+            x:      data 3
+            n:      data 3
+        ").unwrap()
+    }
+
+    #[test]
+    fn same_results() {
+        let day02 = crate::intcode::parse(&crate::load("02.txt")).unwrap();
+        for noun in (0..100).step_by(7) {
+            for verb in (0..100).step_by(11) {
+                let mut program = day02.clone();
+                program[1] = noun;
+                program[2] = verb;
+                differential(&program, &[]);
+            }
+        }
+
+        let day05 = crate::intcode::parse(&crate::load("05.txt")).unwrap();
+        differential(&day05, &[1]);
+        differential(&day05, &[5]);
+
+        differential(&self_modifying(), &[]);
+        differential(&[1101, 1, 98, 0, 99], &[]);
+        differential(&[3, 0, 4, 0, 99], &[]);
+        differential(&[3, 100, 1005, 100, 0, 99], &[7]);
+        differential(&[109, -1, 204, 0, 99], &[]);
+    }
+
+    #[test]
+    fn invalidates_on_write() {
+        let prog = self_modifying();
+        let mut vm = VM::new(&prog);
+        vm.predecode();
+        let (outputs, end, _, _) = run(vm, &[]);
+        // 3+3, then op 2 (mul) 6*6, then op 3 is a read
+        assert_eq!(outputs, vec![6, 36]);
+        assert_eq!(end, Ok(Status::NeedsInput));
+
+        // the shared cache itself is untouched by the VM that modified code
+        let cache = DecodeCache::new(&prog);
+        let mut vm = VM::new(&prog);
+        vm.share_decoded(&cache);
+        assert_eq!(run(vm, &[]).0, vec![6, 36]);
+        assert_eq!(cache.get(0, &prog[0]), Some(Op::Add(Mode::Ptr, Mode::Ptr, Mode::Ptr)));
+        assert_eq!(cache.get(0, &2), None);
+    }
+}
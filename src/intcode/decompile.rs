@@ -0,0 +1,704 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use super::cfg::{Cfg, Exit};
+use super::disasm::Line;
+use super::{Mode, Op};
+
+// stands in for "leaves the function" when computing post-dominators
+const EXIT: usize = usize::MAX;
+
+// a natural loop: the header and every block that can get back to it
+// without passing through it
+struct Loop {
+    body: BTreeSet<usize>,
+    exit: Option<usize>,
+}
+
+enum Out {
+    Line(usize, String),
+    // where a block starts, printed only if something jumps to it
+    Label(usize, usize),
+}
+
+// lifts a program into pseudocode: one function per call target, loops and
+// if/else recovered from the control-flow graph, and `goto` wherever the
+// structure doesn't fit. Cells are `v<addr>` unless named, words of code are
+// `mem[<addr>]` and relative parameters are `rb[<offset>]`.
+pub struct Decompiler {
+    cfg: Cfg,
+    names: BTreeMap<usize, String>,
+    code: BTreeSet<usize>,
+}
+
+pub fn decompile(prog: &[isize]) -> String {
+    Decompiler::new(prog).source()
+}
+
+impl Decompiler {
+    pub fn new(prog: &[isize]) -> Self {
+        let cfg = Cfg::new(prog);
+        let code = cfg.blocks.values()
+            .flat_map(|b| b.lines.iter())
+            .flat_map(|l| l.addr..l.addr + l.words.len())
+            .collect();
+        Self { cfg, names: BTreeMap::new(), code }
+    }
+
+    pub fn name(&mut self, addr: usize, name: &str) -> &mut Self {
+        self.names.insert(addr, name.to_string());
+        self
+    }
+
+    pub fn cfg(&self) -> &Cfg {
+        &self.cfg
+    }
+
+    pub fn source(&self) -> String {
+        let functions: Vec<String> = self.cfg.functions.iter().map(|f| self.function(*f)).collect();
+        functions.join("\n")
+    }
+
+    pub fn function(&self, entry: usize) -> String {
+        let mut f = Function::new(self, entry);
+        f.region(entry, None, None, 1, false);
+        // code only reachable through a goto
+        while let Some(b) = f.gotos.iter().copied().find(|b| !f.emitted.contains(b) && f.blocks.contains(b)) {
+            f.region(b, None, None, 1, false);
+        }
+
+        let mut s = format!("fn {}() {{\n", self.function_name(entry));
+        for out in &f.out {
+            match out {
+                Out::Line(indent, text) => s += &format!("{}{}\n", "    ".repeat(*indent), text),
+                Out::Label(indent, addr) if f.gotos.contains(addr) => {
+                    s += &format!("{}L{}:\n", "    ".repeat(indent - 1), addr);
+                },
+                Out::Label(_, _) => (),
+            }
+        }
+        s + "}\n"
+    }
+
+    fn function_name(&self, entry: usize) -> String {
+        if entry == 0 {
+            "main".to_string()
+        } else {
+            format!("f{}", entry)
+        }
+    }
+
+    fn cell(&self, addr: isize) -> String {
+        if addr < 0 {
+            return format!("mem[{}]", addr);
+        }
+        let addr = addr as usize;
+        match self.names.get(&addr) {
+            Some(name) => name.clone(),
+            None if self.code.contains(&addr) => format!("mem[{}]", addr),
+            None => format!("v{}", addr),
+        }
+    }
+
+    fn operand(&self, mode: Mode, x: isize) -> String {
+        match mode {
+            Mode::Ptr => self.cell(x),
+            Mode::Imm => x.to_string(),
+            Mode::Rel => format!("rb[{}]", x),
+        }
+    }
+
+    // parameter i of the instruction as an expression
+    fn param(&self, line: &Line, i: usize) -> String {
+        let op = line.op.unwrap();
+        self.operand(op.modes()[i], line.words[i + 1])
+    }
+
+    fn imm(&self, line: &Line, i: usize) -> Option<isize> {
+        match line.op.unwrap().modes()[i] {
+            Mode::Imm => Some(line.words[i + 1]),
+            _ => None,
+        }
+    }
+
+    // `dst = a + b`, shortened to `dst += b` and friends where possible
+    fn assign(&self, dst: String, a: String, sym: &str, b: String) -> String {
+        if dst == a {
+            if sym == "+" && b.starts_with('-') {
+                return format!("{} -= {};", dst, &b[1..]);
+            }
+            return format!("{} {}= {};", dst, sym, b);
+        }
+        if sym == "+" && b.starts_with('-') {
+            return format!("{} = {} - {};", dst, a, &b[1..]);
+        }
+        format!("{} = {} {} {};", dst, a, sym, b)
+    }
+
+    fn statement(&self, line: &Line) -> Option<String> {
+        let op = line.op?;
+        let p = |i| self.param(line, i);
+        let s = match op {
+            Op::Add(_, _, _) => match (self.imm(line, 0), self.imm(line, 1)) {
+                (Some(0), _) => format!("{} = {};", p(2), p(1)),
+                (_, Some(0)) => format!("{} = {};", p(2), p(0)),
+                (Some(_), None) => self.assign(p(2), p(1), "+", p(0)),
+                _ => self.assign(p(2), p(0), "+", p(1)),
+            },
+            Op::Mul(_, _, _) => match (self.imm(line, 0), self.imm(line, 1)) {
+                (Some(1), _) => format!("{} = {};", p(2), p(1)),
+                (_, Some(1)) => format!("{} = {};", p(2), p(0)),
+                (_, Some(-1)) => format!("{} = -{};", p(2), p(0)),
+                (Some(_), None) => self.assign(p(2), p(1), "*", p(0)),
+                _ => self.assign(p(2), p(0), "*", p(1)),
+            },
+            Op::Less(_, _, _) => format!("{} = {} < {};", p(2), p(0), p(1)),
+            Op::Equal(_, _, _) => format!("{} = {} == {};", p(2), p(0), p(1)),
+            Op::Read(_) => format!("{} = input();", p(0)),
+            Op::Write(_) => format!("output({});", p(0)),
+            Op::Rebase(_) => match self.imm(line, 0).and_then(isize::checked_neg) {
+                Some(n) if n > 0 => format!("rb -= {};", n),
+                _ => format!("rb += {};", p(0)),
+            },
+            Op::Ext(spec, _) => {
+                let args: Vec<String> = (0..spec.params.len()).map(p).collect();
+                format!("{}({});", spec.mnemonic, args.join(", "))
+            },
+            Op::Jump(_, _, _) | Op::Halt => return None,
+        };
+        Some(s)
+    }
+
+    // the condition under which a jump goes to its target, or falls through
+    fn condition(&self, line: &Line, taken: bool) -> String {
+        let nonzero = match line.op {
+            Some(Op::Jump(m, _, _)) => m == taken,
+            _ => panic!("not a jump"),
+        };
+        format!("{} {} 0", self.param(line, 0), if nonzero { "!=" } else { "==" })
+    }
+}
+
+struct Function<'d> {
+    d: &'d Decompiler,
+    blocks: BTreeSet<usize>,
+    loops: BTreeMap<usize, Rc<Loop>>,
+    ipdom: BTreeMap<usize, usize>,
+    emitted: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+    out: Vec<Out>,
+}
+
+impl<'d> Function<'d> {
+    fn new(d: &'d Decompiler, entry: usize) -> Self {
+        let mut f = Self {
+            d,
+            blocks: BTreeSet::new(),
+            loops: BTreeMap::new(),
+            ipdom: BTreeMap::new(),
+            emitted: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+            out: Vec::new(),
+        };
+        let mut queue = vec![entry];
+        while let Some(b) = queue.pop() {
+            if d.cfg.blocks.contains_key(&b) && f.blocks.insert(b) {
+                queue.extend(d.cfg.blocks[&b].exit.targets());
+            }
+        }
+        f.find_loops(entry);
+        f.postdominators();
+        f
+    }
+
+    fn succs(&self, b: usize) -> Vec<usize> {
+        self.d.cfg.blocks[&b].exit.targets().into_iter()
+            .filter(|s| self.blocks.contains(s))
+            .collect()
+    }
+
+    fn preds(&self, b: usize) -> Vec<usize> {
+        self.blocks.iter().copied().filter(|p| self.succs(*p).contains(&b)).collect()
+    }
+
+    fn find_loops(&mut self, entry: usize) {
+        // depth first, an edge to a block still on the stack closes a loop
+        let mut back: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut visited = BTreeSet::new();
+        let mut on_stack = BTreeSet::new();
+        let mut stack = vec![(entry, 0)];
+        visited.insert(entry);
+        on_stack.insert(entry);
+        while let Some((b, i)) = stack.pop() {
+            let succs = self.succs(b);
+            if i == succs.len() {
+                on_stack.remove(&b);
+                continue;
+            }
+            stack.push((b, i + 1));
+            let s = succs[i];
+            if on_stack.contains(&s) {
+                back.entry(s).or_default().push(b);
+            } else if visited.insert(s) {
+                on_stack.insert(s);
+                stack.push((s, 0));
+            }
+        }
+
+        for (header, tails) in back {
+            let mut body = BTreeSet::new();
+            body.insert(header);
+            let mut queue = tails;
+            while let Some(b) = queue.pop() {
+                if body.insert(b) {
+                    queue.extend(self.preds(b));
+                }
+            }
+            // prefer the way out tested by the header, as in a while loop
+            let outside = |b: &usize| !body.contains(b);
+            let exit = self.succs(header).into_iter().find(outside).or_else(|| {
+                body.iter().flat_map(|b| self.succs(*b)).filter(outside).min()
+            });
+            self.loops.insert(header, Rc::new(Loop { body, exit }));
+        }
+    }
+
+    fn postdominators(&mut self) {
+        let exits = |f: &Self, b: usize| -> Vec<usize> {
+            let mut s = f.succs(b);
+            match f.d.cfg.blocks[&b].exit {
+                Exit::Halt | Exit::Fault | Exit::Return | Exit::Indirect { .. } => s.push(EXIT),
+                _ => (),
+            }
+            s
+        };
+        let mut all: BTreeSet<usize> = self.blocks.clone();
+        all.insert(EXIT);
+        let mut pdom: BTreeMap<usize, BTreeSet<usize>> = self.blocks.iter().map(|b| (*b, all.clone())).collect();
+        pdom.insert(EXIT, [EXIT].iter().copied().collect());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in self.blocks.iter().rev() {
+                let mut set = exits(self, *b).iter()
+                    .map(|s| pdom[s].clone())
+                    .reduce(|a, s| a.intersection(&s).copied().collect())
+                    .unwrap_or_else(|| all.clone());
+                set.insert(*b);
+                if set != pdom[b] {
+                    pdom.insert(*b, set);
+                    changed = true;
+                }
+            }
+        }
+        // blocks stuck in an endless loop keep the full set and get no join
+        let mut leaves: BTreeSet<usize> = self.blocks.iter().copied()
+            .filter(|b| exits(self, *b).contains(&EXIT))
+            .collect();
+        let mut queue: Vec<usize> = leaves.iter().copied().collect();
+        while let Some(b) = queue.pop() {
+            for p in self.preds(b) {
+                if leaves.insert(p) {
+                    queue.push(p);
+                }
+            }
+        }
+        for b in &self.blocks {
+            if !leaves.contains(b) {
+                continue;
+            }
+            // post-dominators form a chain, so the nearest has the most
+            let nearest = pdom[b].iter()
+                .filter(|p| *p != b)
+                .max_by_key(|p| pdom[p].len());
+            if let Some(p) = nearest {
+                if *p != EXIT {
+                    self.ipdom.insert(*b, *p);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, indent: usize, text: impl Into<String>) {
+        self.out.push(Out::Line(indent, text.into()));
+    }
+
+    fn goto(&mut self, indent: usize, b: usize) {
+        if self.d.cfg.blocks.contains_key(&b) {
+            self.gotos.insert(b);
+            self.line(indent, format!("goto L{};", b));
+        } else {
+            self.line(indent, format!("goto {}; // not code", b));
+        }
+    }
+
+    fn drop_trailing_continue(&mut self, indent: usize) {
+        if let Some(Out::Line(i, text)) = self.out.last() {
+            if *i == indent && text == "continue;" {
+                self.out.pop();
+            }
+        }
+    }
+
+    // emits blocks starting at b until reaching stop; ctx is the innermost
+    // loop and its header, enter is set when b is that header being emitted
+    // as the first block of the loop body
+    fn region(&mut self, mut b: usize, stop: Option<usize>, ctx: Option<(usize, Rc<Loop>)>, indent: usize, mut enter: bool) {
+        loop {
+            if Some(b) == stop {
+                return;
+            }
+            if let Some((header, l)) = &ctx {
+                if Some(b) == l.exit {
+                    self.line(indent, "break;");
+                    return;
+                }
+                if b == *header && !enter {
+                    self.line(indent, "continue;");
+                    return;
+                }
+                if !l.body.contains(&b) {
+                    self.goto(indent, b);
+                    return;
+                }
+            }
+            if !self.blocks.contains(&b) || self.emitted.contains(&b) {
+                self.goto(indent, b);
+                return;
+            }
+            let entering_loop = match (&ctx, self.loops.get(&b)) {
+                (Some((header, _)), Some(_)) if *header == b && enter => false,
+                (_, Some(_)) => true,
+                _ => false,
+            };
+            if entering_loop {
+                match self.emit_loop(b, indent) {
+                    Some(exit) => b = exit,
+                    None => return,
+                }
+                continue;
+            }
+            enter = false;
+
+            self.emitted.insert(b);
+            self.out.push(Out::Label(indent, b));
+            let block = &self.d.cfg.blocks[&b];
+            let exit = block.exit;
+            let last = block.lines.last().unwrap().clone();
+            for line in &block.lines {
+                // the return address pushed for a call is implied by it
+                if let Exit::Call { ret, .. } = exit {
+                    let words = &line.words;
+                    let pushes_ret = match line.op {
+                        Some(Op::Add(Mode::Imm, Mode::Imm, _)) => words[1].checked_add(words[2]) == Some(ret as isize),
+                        Some(Op::Mul(Mode::Imm, Mode::Imm, _)) => words[1].checked_mul(words[2]) == Some(ret as isize),
+                        _ => false,
+                    };
+                    if pushes_ret {
+                        continue;
+                    }
+                }
+                if let Some(s) = self.d.statement(line) {
+                    self.line(indent, s);
+                }
+            }
+
+            match exit {
+                Exit::Halt => {
+                    self.line(indent, "halt;");
+                    return;
+                },
+                Exit::Return => {
+                    self.line(indent, "return;");
+                    return;
+                },
+                Exit::Fault => {
+                    self.line(indent, format!("goto {}; // out of range", last.words[2]));
+                    return;
+                },
+                Exit::Indirect { fall } => {
+                    let target = self.d.param(&last, 1);
+                    match fall {
+                        Some(fall) => {
+                            let cond = self.d.condition(&last, true);
+                            self.line(indent, format!("if {} {{", cond));
+                            self.line(indent + 1, format!("goto *{};", target));
+                            self.line(indent, "}");
+                            b = fall;
+                        },
+                        None => {
+                            self.line(indent, format!("goto *{};", target));
+                            return;
+                        },
+                    }
+                },
+                Exit::Call { target, ret } => {
+                    self.line(indent, format!("{}();", self.d.function_name(target)));
+                    b = ret;
+                },
+                Exit::Goto(to) => b = to,
+                Exit::Branch { taken, fall } => {
+                    // a test that leaves or restarts the loop
+                    if let Some((header, l)) = &ctx {
+                        let (to, other, taken_to) = if Some(taken) == l.exit {
+                            ("break;", fall, true)
+                        } else if Some(fall) == l.exit {
+                            ("break;", taken, false)
+                        } else if taken == *header {
+                            ("continue;", fall, true)
+                        } else if fall == *header {
+                            ("continue;", taken, false)
+                        } else {
+                            ("", 0, false)
+                        };
+                        if !to.is_empty() {
+                            let cond = self.d.condition(&last, taken_to);
+                            self.line(indent, format!("if {} {{", cond));
+                            self.line(indent + 1, to);
+                            self.line(indent, "}");
+                            b = other;
+                            continue;
+                        }
+                    }
+
+                    let join = self.ipdom.get(&b).copied();
+                    if Some(taken) == join {
+                        self.line(indent, format!("if {} {{", self.d.condition(&last, false)));
+                        self.region(fall, join, ctx.clone(), indent + 1, false);
+                    } else if Some(fall) == join {
+                        self.line(indent, format!("if {} {{", self.d.condition(&last, true)));
+                        self.region(taken, join, ctx.clone(), indent + 1, false);
+                    } else {
+                        self.line(indent, format!("if {} {{", self.d.condition(&last, false)));
+                        self.region(fall, join, ctx.clone(), indent + 1, false);
+                        self.line(indent, "} else {");
+                        self.region(taken, join, ctx.clone(), indent + 1, false);
+                    }
+                    self.line(indent, "}");
+                    match join {
+                        Some(j) => b = j,
+                        None => return,
+                    }
+                },
+            }
+        }
+    }
+
+    // emits the loop headed by b, returning where it exits to
+    fn emit_loop(&mut self, b: usize, indent: usize) -> Option<usize> {
+        let l = self.loops[&b].clone();
+        let block = &self.d.cfg.blocks[&b];
+        let ctx = Some((b, l.clone()));
+
+        // a header that only tests is a while loop
+        let test = match block.exit {
+            Exit::Branch { taken, fall } if block.lines.len() == 1 => {
+                if Some(taken) == l.exit && l.body.contains(&fall) {
+                    Some((fall, false))
+                } else if Some(fall) == l.exit && l.body.contains(&taken) {
+                    Some((taken, true))
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        };
+        match test {
+            Some((stay, taken)) => {
+                let cond = self.d.condition(&block.lines[0], taken);
+                self.emitted.insert(b);
+                self.out.push(Out::Label(indent + 1, b));
+                self.line(indent, format!("while {} {{", cond));
+                self.region(stay, None, ctx, indent + 1, false);
+            },
+            None => {
+                self.line(indent, "loop {");
+                self.region(b, None, ctx, indent + 1, true);
+            },
+        }
+        self.drop_trailing_continue(indent + 1);
+        self.line(indent, "}");
+        l.exit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    #[test]
+    fn while_loop() {
+        let prog = assemble("
+                    read [n]
+            loop:   jz [n], done
+                    add [n], -1, [n]
+                    jnz 1, loop
+            done:   write [n]
+                    add 99, 0, [fin]
+                    jnz [n], [ret]
+            fin:    halt
+            n:      data 0
+            ret:    data 21
+        ").unwrap();
+        assert_eq!(decompile(&prog), [
+            "fn main() {",
+            "    v22 = input();",
+            "    while v22 != 0 {",
+            "        v22 -= 1;",
+            "    }",
+            "    output(v22);",
+            "    mem[21] = 99;",
+            "    if v22 != 0 {",
+            "        goto *v23;",
+            "    }",
+            "    halt;",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn if_else() {
+        let prog = assemble("
+                    read [x]
+                    less [x], 10, [t]
+                    jz [t], big
+                    write 1
+                    jz 0, end
+            big:    write 2
+            end:    halt
+            x:      data 0
+            t:      data 0
+        ").unwrap();
+        let mut d = Decompiler::new(&prog);
+        d.name(17, "x").name(18, "small");
+        assert_eq!(d.source(), [
+            "fn main() {",
+            "    x = input();",
+            "    small = x < 10;",
+            "    if small != 0 {",
+            "        output(1);",
+            "    } else {",
+            "        output(2);",
+            "    }",
+            "    halt;",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn calls() {
+        let prog = assemble("
+                    rebase stack
+                    add ret, 0, [rb]
+                    add 5, 0, [rb+1]
+                    jnz 1, double
+            ret:    write [rb+1]
+                    halt
+            double: rebase 2
+                    mul [rb-1], 2, [rb-1]
+                    rebase -2
+                    jz 0, [rb]
+            stack:  data 0
+        ").unwrap();
+        assert_eq!(decompile(&prog), [
+            "fn main() {",
+            "    rb += 27;",
+            "    rb[1] = 5;",
+            "    f16();",
+            "    output(rb[1]);",
+            "    halt;",
+            "}",
+            "",
+            "fn f16() {",
+            "    rb += 2;",
+            "    rb[-1] *= 2;",
+            "    rb -= 2;",
+            "    return;",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn loop_with_exit_test() {
+        // the body runs before the test, so this isn't a while loop
+        let prog = assemble("
+            loop:   read [x]
+                    write [x]
+                    jnz [x], loop
+                    halt
+            x:      data 0
+        ").unwrap();
+        assert_eq!(decompile(&prog), [
+            "fn main() {",
+            "    loop {",
+            "        v8 = input();",
+            "        output(v8);",
+            "        if v8 == 0 {",
+            "            break;",
+            "        }",
+            "    }",
+            "    halt;",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn nested_loops() {
+        let prog = assemble("
+                    read [n]
+            outer:  jz [n], done
+                    add [n], 0, [m]
+            inner:  jz [m], next
+                    write [m]
+                    add [m], -1, [m]
+                    jz 0, inner
+            next:   add [n], -1, [n]
+                    jz 0, outer
+            done:   halt
+            n:      data 0
+            m:      data 0
+        ").unwrap();
+        assert_eq!(decompile(&prog), [
+            "fn main() {",
+            "    v29 = input();",
+            "    while v29 != 0 {",
+            "        v30 = v29;",
+            "        while v30 != 0 {",
+            "            output(v30);",
+            "            v30 -= 1;",
+            "        }",
+            "        v29 -= 1;",
+            "    }",
+            "    halt;",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn extreme_immediates() {
+        let big = 1 << 40;
+        let source = decompile(&[1102, big, big, 100, 1101, 11, 0, 101, 1105, 1, 12, 99, 99]);
+        assert!(source.contains("v100 = 1099511627776 * 1099511627776;"), "{}", source);
+        let source = decompile(&[109, isize::MIN, 109, -5, 99]);
+        assert!(source.contains(&format!("rb += {};", isize::MIN)), "{}", source);
+        assert!(source.contains("rb -= 5;"), "{}", source);
+    }
+
+    #[test]
+    fn puzzle_input() {
+        let prog = crate::intcode::parse(&crate::load("02.txt")).unwrap();
+        let mut d = Decompiler::new(&prog);
+        d.name(0, "result").name(1, "noun").name(2, "verb");
+        let source = d.source();
+        // the image as shipped adds address 0 to itself before the noun and
+        // verb are patched in
+        assert!(source.starts_with("fn main() {\n    mem[3] = result + result;\n    mem[3] = noun + verb;\n"));
+        assert!(source.ends_with("    result = mem[107] + mem[10];\n    halt;\n}\n"));
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::ext::{self, Spec};
+use super::{Mode, Op};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: usize,
+    pub words: Vec<isize>,
+    // None for words that are treated as data
+    pub op: Option<Op>,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        match self.op {
+            Some(op) => {
+                let operands: Vec<String> = op.modes().into_iter()
+                    .zip(&self.words[1..])
+                    .map(|(mode, x)| operand(mode, *x))
+                    .collect();
+                if operands.is_empty() {
+                    op.mnemonic().to_string()
+                } else {
+                    format!("{} {}", op.mnemonic(), operands.join(", "))
+                }
+            },
+            None => {
+                let words: Vec<String> = self.words.iter().map(|x| x.to_string()).collect();
+                format!("data {}", words.join(", "))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words: Vec<String> = self.words.iter().map(|x| x.to_string()).collect();
+        write!(f, "{:04}  {:<24}  {}", self.addr, words.join(" "), self.text())
+    }
+}
+
+pub fn operand(mode: Mode, x: isize) -> String {
+    match mode {
+        Mode::Ptr => format!("[{}]", x),
+        Mode::Imm => x.to_string(),
+        Mode::Rel if x < 0 => format!("[rb{}]", x),
+        Mode::Rel => format!("[rb+{}]", x),
+    }
+}
+
+// decodes the instruction at addr if it fits inside the program
+pub(super) fn decode(prog: &[isize], addr: usize) -> Option<Op> {
+    decode_with(prog, addr, &[])
+}
+
+fn decode_with(prog: &[isize], addr: usize, specs: &[Spec]) -> Option<Op> {
+    let op = ext::decode(prog[addr], specs)?;
+    if op.is_valid() && addr + op.size() <= prog.len() {
+        Some(op)
+    } else {
+        None
+    }
+}
+
+pub(super) fn line(prog: &[isize], addr: usize, op: Option<Op>) -> Line {
+    let len = op.map_or(1, |op| op.size());
+    Line { addr, words: prog[addr..addr + len].to_vec(), op }
+}
+
+// linear sweep: every word that decodes is assumed to be an instruction
+pub fn disassemble(prog: &[isize]) -> Vec<Line> {
+    disassemble_with(prog, &[])
+}
+
+// the _with variants also decode the given extension instructions
+pub fn disassemble_with(prog: &[isize], specs: &[Spec]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < prog.len() {
+        let l = line(prog, addr, decode_with(prog, addr, specs));
+        addr += l.words.len();
+        lines.push(l);
+    }
+    lines
+}
+
+// addresses of instructions reachable from the entry point, following
+// immediate jump targets; pointer and relative jumps can't be resolved
+pub fn reachable(prog: &[isize]) -> BTreeSet<usize> {
+    reachable_with(prog, &[])
+}
+
+// extensions are assumed to continue with the next instruction
+pub fn reachable_with(prog: &[isize], specs: &[Spec]) -> BTreeSet<usize> {
+    let mut code = BTreeSet::new();
+    let mut queue = vec![0];
+    while let Some(addr) = queue.pop() {
+        if addr >= prog.len() || code.contains(&addr) {
+            continue;
+        }
+        let op = match decode_with(prog, addr, specs) {
+            Some(op) => op,
+            None => continue,
+        };
+        code.insert(addr);
+
+        match op {
+            Op::Halt => (),
+            Op::Jump(m, a_mode, dst_mode) => {
+                let a = prog[addr + 1];
+                let dst = prog[addr + 2];
+                let always = matches!(a_mode, Mode::Imm) && (a != 0) == m;
+                let never = matches!(a_mode, Mode::Imm) && (a != 0) != m;
+                if !never && matches!(dst_mode, Mode::Imm) && dst >= 0 {
+                    queue.push(dst as usize);
+                }
+                if !always {
+                    queue.push(addr + op.size());
+                }
+            },
+            _ => queue.push(addr + op.size()),
+        }
+    }
+    code
+}
+
+// recursive traversal: only reachable words are decoded, everything else is
+// grouped into data lines
+pub fn disassemble_reachable(prog: &[isize]) -> Vec<Line> {
+    disassemble_code(prog, &reachable(prog))
+}
+
+pub fn disassemble_reachable_with(prog: &[isize], specs: &[Spec]) -> Vec<Line> {
+    disassemble_code_with(prog, &reachable_with(prog, specs), specs)
+}
+
+// decodes instructions only at the given addresses
+pub fn disassemble_code(prog: &[isize], code: &BTreeSet<usize>) -> Vec<Line> {
+    disassemble_code_with(prog, code, &[])
+}
+
+pub fn disassemble_code_with(prog: &[isize], code: &BTreeSet<usize>, specs: &[Spec]) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut addr = 0;
+    while addr < prog.len() {
+        if code.contains(&addr) {
+            let l = line(prog, addr, decode_with(prog, addr, specs));
+            addr += l.words.len();
+            lines.push(l);
+            continue;
+        }
+        match lines.last_mut() {
+            Some(l) if l.op.is_none() => l.words.push(prog[addr]),
+            _ => lines.push(line(prog, addr, None)),
+        }
+        addr += 1;
+    }
+    lines
+}
+
+pub fn listing(lines: &[Line]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+// just the instruction text, which the assembler accepts back
+pub fn source(lines: &[Line]) -> String {
+    lines.iter().map(|l| format!("{}\n", l.text())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CMP_JMP: [isize; 47] = [
+        3, 21,
+        1008, 21, 8, 20,
+        1005, 20, 22,
+        107, 8, 21, 20,
+        1006, 20, 31,
+        1106, 0, 36,
+        98, 0, 0,
+        1002, 21, 125, 20,
+        4, 20,
+        1105, 1, 46,
+        104, 999,
+        1105, 1, 46,
+        1101, 1000, 1, 20,
+        4, 20,
+        1105, 1, 46,
+        98,
+        99,
+    ];
+
+    #[test]
+    fn linear() {
+        let lines = disassemble(&CMP_JMP);
+        assert_eq!(lines[0].to_string(), "0000  3 21                      read [21]");
+        assert_eq!(lines[1].to_string(), "0002  1008 21 8 20              equal [21], 8, [20]");
+        assert_eq!(lines[2].text(), "jnz [20], 22");
+        assert_eq!(lines[3].text(), "less 8, [21], [20]");
+        assert_eq!(lines[4].text(), "jz [20], 31");
+        assert_eq!(lines[5].text(), "jz 0, 36");
+        assert_eq!(lines[6].text(), "data 98");
+        assert_eq!(lines[7].text(), "data 0");
+        assert_eq!(lines.last().unwrap().text(), "halt");
+    }
+
+    #[test]
+    fn modes() {
+        let lines = disassemble(&[21201, -1, 5, 3, 209, 4, 203, -2, 1]);
+        assert_eq!(lines[0].text(), "add [rb-1], 5, [rb+3]");
+        assert_eq!(lines[1].text(), "rebase [rb+4]");
+        assert_eq!(lines[2].text(), "read [rb-2]");
+        // truncated instructions are data
+        assert_eq!(lines[3].text(), "data 1");
+
+        // so are writes to immediate parameters
+        let lines = disassemble(&[10001, 1, 2, 3]);
+        assert_eq!(lines[0].text(), "data 10001");
+    }
+
+    #[test]
+    fn follows_jumps() {
+        let prog = [1105, 1, 7, 1, 2, 3, 4, 104, 3, 99];
+        let linear: Vec<String> = disassemble(&prog).iter().map(|l| l.text()).collect();
+        assert_eq!(linear, vec!["jnz 1, 7", "add [2], [3], [4]", "write 3", "halt"]);
+
+        let lines: Vec<String> = disassemble_reachable(&prog).iter().map(|l| l.text()).collect();
+        assert_eq!(lines, vec!["jnz 1, 7", "data 1, 2, 3, 4", "write 3", "halt"]);
+
+        let lines = disassemble_reachable(&CMP_JMP);
+        let data: Vec<usize> = lines.iter()
+            .filter(|l| l.op.is_none())
+            .map(|l| l.addr)
+            .collect();
+        assert_eq!(data, vec![19, 45]);
+        assert!(listing(&lines).contains("0019  98 0 0                    data 98, 0, 0\n"));
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::word::Word;
+use super::{Mode, Op, VM, VmError};
+
+// how an extension uses a parameter
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Param {
+    // read, in any mode, before the callback runs
+    In,
+    // written after the callback runs, if it set a value; never immediate
+    Out,
+}
+
+// what the decoder, assembler and disassembler need to know about an extra
+// op code, e.g. Spec { code: 10, mnemonic: "mod", params: &[In, In, Out] }
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Spec {
+    pub code: isize,
+    pub mnemonic: &'static str,
+    pub params: &'static [Param],
+}
+
+impl Spec {
+    // the instruction for a word with this op code, following the same mode
+    // digit rules as the built in set
+    pub fn decode(&self, x: isize) -> Option<Op> {
+        if x < 0 || x % 100 != self.code {
+            return None;
+        }
+        let mut digits = x / 100;
+        let mut modes = [Mode::Ptr; 3];
+        for m in modes.iter_mut().take(self.params.len()) {
+            *m = Mode::from(digits % 10)?;
+            digits /= 10;
+        }
+        if digits != 0 {
+            return None;
+        }
+        Some(Op::Ext(*self, modes))
+    }
+
+    // the instruction with the given modes, one per parameter
+    pub fn op(&self, modes: &[Mode]) -> Option<Op> {
+        if modes.len() != self.params.len() {
+            return None;
+        }
+        let mut m = [Mode::Ptr; 3];
+        m[..modes.len()].copy_from_slice(modes);
+        Some(Op::Ext(*self, m))
+    }
+}
+
+// built in instructions first, then the given extensions
+pub fn decode(x: isize, specs: &[Spec]) -> Option<Op> {
+    Op::from(x).or_else(|| specs.iter().find_map(|s| s.decode(x)))
+}
+
+// an extension instruction as seen by its callback
+#[derive(Clone, Debug)]
+pub struct Call<W = isize> {
+    pub pc: usize,
+    // the relative base, which the callback may change
+    pub base: isize,
+    params: &'static [Param],
+    values: Vec<W>,
+    stored: Vec<bool>,
+    jump: Option<usize>,
+}
+
+impl<W: Word> Call<W> {
+    // the value of an In parameter
+    pub fn arg(&self, i: usize) -> &W {
+        assert_eq!(self.params[i], Param::In, "parameter {} is not an input", i);
+        &self.values[i]
+    }
+
+    // sets the value written to an Out parameter
+    pub fn set(&mut self, i: usize, value: W) {
+        assert_eq!(self.params[i], Param::Out, "parameter {} is not an output", i);
+        self.values[i] = value;
+        self.stored[i] = true;
+    }
+
+    // continues at addr instead of the next instruction
+    pub fn jump(&mut self, addr: usize) {
+        self.jump = Some(addr);
+    }
+}
+
+type Exec<W> = Arc<dyn Fn(&mut Call<W>) -> Result<(), &'static str> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Extension<W = isize> {
+    pub spec: Spec,
+    exec: Exec<W>,
+}
+
+impl<W> fmt::Debug for Extension<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extension({:?})", self.spec)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExtError {
+    // op codes are two digits and 0 is never valid
+    OutOfRange(isize),
+    // taken by a built in instruction
+    Reserved(isize),
+    Duplicate(isize),
+    DuplicateMnemonic(&'static str),
+    // at most three parameters, like the built in set
+    TooManyParams(isize),
+}
+
+impl fmt::Display for ExtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfRange(code) => write!(f, "op code {} is not between 1 and 99", code),
+            Self::Reserved(code) => write!(f, "op code {} is built in", code),
+            Self::Duplicate(code) => write!(f, "op code {} is already registered", code),
+            Self::DuplicateMnemonic(m) => write!(f, "mnemonic `{}` is already in use", m),
+            Self::TooManyParams(code) => write!(f, "op code {} takes more than 3 parameters", code),
+        }
+    }
+}
+
+impl Error for ExtError {}
+
+// extra op codes and their callbacks; clones share the callbacks, so one set
+// can be registered once and handed to many VMs
+#[derive(Clone, Debug)]
+pub struct Extensions<W = isize> {
+    ext: BTreeMap<isize, Extension<W>>,
+}
+
+impl<W> Default for Extensions<W> {
+    fn default() -> Self {
+        Self { ext: BTreeMap::new() }
+    }
+}
+
+impl<W: Word> Extensions<W> {
+    pub fn register<F>(&mut self, spec: Spec, exec: F) -> Result<(), ExtError>
+    where
+        F: Fn(&mut Call<W>) -> Result<(), &'static str> + Send + Sync + 'static,
+    {
+        if !(1..100).contains(&spec.code) {
+            return Err(ExtError::OutOfRange(spec.code));
+        }
+        if Op::arity(spec.code).is_some() {
+            return Err(ExtError::Reserved(spec.code));
+        }
+        if self.ext.contains_key(&spec.code) {
+            return Err(ExtError::Duplicate(spec.code));
+        }
+        if spec.params.len() > 3 {
+            return Err(ExtError::TooManyParams(spec.code));
+        }
+        let builtin = super::asm::mnemonics().contains(&spec.mnemonic);
+        if builtin || spec.mnemonic == "data" || self.ext.values().any(|e| e.spec.mnemonic == spec.mnemonic) {
+            return Err(ExtError::DuplicateMnemonic(spec.mnemonic));
+        }
+        self.ext.insert(spec.code, Extension { spec, exec: Arc::new(exec) });
+        Ok(())
+    }
+
+    pub fn get(&self, code: isize) -> Option<&Extension<W>> {
+        self.ext.get(&code)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ext.is_empty()
+    }
+
+    // for the assembler and disassembler
+    pub fn specs(&self) -> Vec<Spec> {
+        self.ext.values().map(|e| e.spec).collect()
+    }
+
+    // an extension instruction, if the word is one
+    pub fn decode(&self, x: isize) -> Option<Op> {
+        self.ext.get(&(x % 100))?.spec.decode(x)
+    }
+}
+
+impl<W: Word> VM<W> {
+    // adds an op code, which VMs without it reject as invalid
+    pub fn register<F>(&mut self, spec: Spec, exec: F) -> Result<(), ExtError>
+    where
+        F: Fn(&mut Call<W>) -> Result<(), &'static str> + Send + Sync + 'static,
+    {
+        self.extensions.register(spec, exec)
+    }
+
+    pub fn extensions(&self) -> &Extensions<W> {
+        &self.extensions
+    }
+
+    pub fn set_extensions(&mut self, extensions: Extensions<W>) {
+        self.extensions = extensions;
+    }
+
+    // parameters go through the usual reads and writes, so watches, traces
+    // and errors treat them like those of built in instructions
+    pub(super) fn extension(&mut self, spec: Spec, modes: [Mode; 3]) -> Result<(), VmError> {
+        // a shared decode cache may hold an op decoded under another VM's
+        // registration of the same code
+        let exec = match self.extensions.get(spec.code) {
+            Some(e) if e.spec == spec => e.exec.clone(),
+            _ => return Err(VmError::InvalidOpcode { pc: self.op_pc, op_code: spec.code }),
+        };
+        let n = spec.params.len();
+        let ptrs: Vec<W> = (0..n).map(|i| self.mem.get(self.pc + i)).collect();
+        self.pc += n;
+        let mut values = Vec::with_capacity(n);
+        for (i, p) in spec.params.iter().enumerate() {
+            values.push(match p {
+                Param::In => self.deref(&ptrs[i], modes[i])?,
+                Param::Out => W::default(),
+            });
+        }
+        let mut call = Call {
+            pc: self.op_pc,
+            base: self.base,
+            params: spec.params,
+            values,
+            stored: vec![false; n],
+            jump: None,
+        };
+        exec(&mut call).map_err(|msg| VmError::Extension { pc: self.op_pc, msg })?;
+        for i in 0..n {
+            if call.stored[i] {
+                self.put(&ptrs[i], modes[i], call.values[i].clone())?;
+            }
+        }
+        self.base = call.base;
+        if let Some(addr) = call.jump {
+            self.pc = addr;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::{assemble, assemble_with};
+    use crate::intcode::disasm::{disassemble, disassemble_reachable_with, disassemble_with};
+    use crate::intcode::Status;
+    use Param::{In, Out};
+
+    const MOD: Spec = Spec { code: 10, mnemonic: "mod", params: &[In, In, Out] };
+    const DIV: Spec = Spec { code: 11, mnemonic: "div", params: &[In, In, Out] };
+    // push x, [rb] and pop [rb-1], [x] with the relative base as stack pointer
+    const PUSH: Spec = Spec { code: 12, mnemonic: "push", params: &[In, Out] };
+    const POP: Spec = Spec { code: 13, mnemonic: "pop", params: &[In, Out] };
+    // jumps to the second parameter if the first is negative
+    const JNEG: Spec = Spec { code: 14, mnemonic: "jneg", params: &[In, In] };
+
+    fn arith() -> Extensions {
+        let mut ext = Extensions::default();
+        ext.register(MOD, |c: &mut Call| match c.arg(1) {
+            0 => Err("division by zero"),
+            y => {
+                let v = c.arg(0).rem_euclid(*y);
+                c.set(2, v);
+                Ok(())
+            },
+        }).unwrap();
+        ext.register(DIV, |c: &mut Call| match c.arg(1) {
+            0 => Err("division by zero"),
+            y => {
+                let v = c.arg(0).div_euclid(*y);
+                c.set(2, v);
+                Ok(())
+            },
+        }).unwrap();
+        ext
+    }
+
+    fn stack() -> Extensions {
+        let mut ext = Extensions::default();
+        ext.register(PUSH, |c: &mut Call| {
+            let v = *c.arg(0);
+            c.set(1, v);
+            c.base += 1;
+            Ok(())
+        }).unwrap();
+        ext.register(POP, |c: &mut Call| {
+            let v = *c.arg(0);
+            c.set(1, v);
+            c.base -= 1;
+            Ok(())
+        }).unwrap();
+        ext.register(JNEG, |c: &mut Call| {
+            if *c.arg(0) < 0 {
+                c.jump(*c.arg(1) as usize);
+            }
+            Ok(())
+        }).unwrap();
+        ext
+    }
+
+    const DIVMOD: &str = "
+                read [a]
+                read [b]
+                mod [a], [b], [r]
+                write [r]
+                div [a], [b], [r]
+                write [r]
+                halt
+        a:      data 0
+        b:      data 0
+        r:      data 0
+    ";
+
+    fn run(vm: &mut VM, inputs: &[isize]) -> Result<Vec<isize>, VmError> {
+        let mut inputs = inputs.iter();
+        let mut outputs = Vec::new();
+        loop {
+            match vm.run()? {
+                Status::Output(x) => outputs.push(x),
+                Status::NeedsInput => vm.push_input(*inputs.next().unwrap()),
+                _ => return Ok(outputs),
+            }
+        }
+    }
+
+    #[test]
+    fn register() {
+        let mut ext = arith();
+        let nop = |_: &mut Call| Ok(());
+        assert_eq!(ext.register(Spec { code: 0, ..MOD }, nop), Err(ExtError::OutOfRange(0)));
+        assert_eq!(ext.register(Spec { code: 110, ..MOD }, nop), Err(ExtError::OutOfRange(110)));
+        assert_eq!(ext.register(Spec { code: 9, mnemonic: "x", ..MOD }, nop), Err(ExtError::Reserved(9)));
+        assert_eq!(ext.register(Spec { mnemonic: "x", ..MOD }, nop), Err(ExtError::Duplicate(10)));
+        assert_eq!(ext.register(Spec { code: 20, ..MOD }, nop), Err(ExtError::DuplicateMnemonic("mod")));
+        assert_eq!(ext.register(Spec { code: 20, mnemonic: "add", ..MOD }, nop), Err(ExtError::DuplicateMnemonic("add")));
+        assert_eq!(ext.register(Spec { code: 20, mnemonic: "data", ..MOD }, nop), Err(ExtError::DuplicateMnemonic("data")));
+        let params = &[In, In, In, Out];
+        assert_eq!(ext.register(Spec { code: 20, mnemonic: "x", params }, nop), Err(ExtError::TooManyParams(20)));
+        assert_eq!(ext.specs(), [MOD, DIV]);
+        assert_eq!(ExtError::Reserved(9).to_string(), "op code 9 is built in");
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(MOD.decode(21010), Some(Op::Ext(MOD, [Mode::Ptr, Mode::Imm, Mode::Rel])));
+        assert_eq!(MOD.decode(10), Some(Op::Ext(MOD, [Mode::Ptr; 3])));
+        assert_eq!(MOD.decode(2001010), None);
+        assert_eq!(MOD.decode(11), None);
+        assert_eq!(JNEG.decode(1114), Some(Op::Ext(JNEG, [Mode::Imm, Mode::Imm, Mode::Ptr])));
+        assert_eq!(JNEG.decode(11114), None);
+        assert_eq!(super::decode(1101, &[MOD]), Op::from(1101));
+        assert!(!MOD.decode(11110).unwrap().is_valid());
+        assert!(JNEG.decode(1114).unwrap().is_valid());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let program = assemble_with(DIVMOD, &[MOD, DIV]).unwrap();
+
+        let mut vm = VM::new(&program);
+        assert_eq!(run(&mut vm, &[17, 5]), Err(VmError::InvalidOpcode { pc: 4, op_code: 10 }));
+
+        let mut vm = VM::new(&program);
+        vm.set_extensions(arith());
+        assert_eq!(run(&mut vm, &[-17, 5]), Ok(vec![3, -4]));
+
+        let mut vm = VM::new(&program);
+        vm.set_extensions(arith());
+        let err = run(&mut vm, &[17, 0]).unwrap_err();
+        assert_eq!(err, VmError::Extension { pc: 4, msg: "division by zero" });
+        assert_eq!(err.to_string(), "division by zero at 4");
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn stack_ops() {
+        let specs = stack().specs();
+        let program = assemble_with("
+                    rebase stack
+                    push 7, [rb]
+                    push [x], [rb]
+                    pop [rb-1], [x]
+                    write [x]
+                    pop [rb-1], [x]
+                    write [x]
+                    jneg -1, end
+                    write 0
+            end:    halt
+            x:      data 8
+            stack:  data 0, 0
+        ", &specs).unwrap();
+        let mut vm = VM::new(&program);
+        vm.set_extensions(stack());
+        assert_eq!(run(&mut vm, &[]), Ok(vec![8, 7]));
+        assert_eq!(vm.base as usize, program.len() - 2);
+
+        let text: Vec<String> = disassemble_reachable_with(&program, &specs).iter().map(|l| l.text()).collect();
+        assert_eq!(text[..3], ["rebase 25", "push 7, [rb+0]", "push [24], [rb+0]"]);
+        assert_eq!(text[7..], ["jneg -1, 23", "write 0", "halt", "data 8, 0, 0"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = assemble_with(DIVMOD, &[MOD, DIV]).unwrap();
+        let source: Vec<String> = disassemble_with(&program, &[MOD, DIV]).iter().map(|l| l.text()).collect();
+        assert_eq!(source[2], "mod [17], [18], [19]");
+        assert_eq!(assemble_with(&source.join("\n"), &[MOD, DIV]).unwrap(), program);
+
+        // without the specs the extension words are data
+        assert_eq!(disassemble(&program)[2].text(), "data 10");
+        let err = assemble(DIVMOD).unwrap_err();
+        assert_eq!(err.to_string(), "line 4: unknown mnemonic `mod`");
+        let err = assemble_with("mod 1, 2, 3", &[MOD]).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: destination of `mod` can't be immediate");
+        let err = assemble_with("mod 1, 2", &[MOD]).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: `mod` takes 3 operands, found 2");
+    }
+
+    #[test]
+    fn shared() {
+        let ext = arith();
+        let program = assemble_with(DIVMOD, &ext.specs()).unwrap();
+        for inputs in &[[7, 2], [100, 7]] {
+            let mut vm = VM::new(&program);
+            vm.set_extensions(ext.clone());
+            vm.predecode();
+            vm.start_profile();
+            let expected = vec![inputs[0] % inputs[1], inputs[0] / inputs[1]];
+            assert_eq!(run(&mut vm, inputs), Ok(expected));
+            let profile = vm.take_profile().unwrap();
+            assert_eq!(profile.ops["mod"], 1);
+            assert_eq!(profile.ops["div"], 1);
+            assert_eq!(vm.decode_cache().unwrap().get(4, &program[4]), MOD.decode(program[4]));
+        }
+        assert!(!ext.is_empty());
+        assert!(Extensions::<isize>::default().is_empty());
+
+        // the same code registered with another arity isn't run from the cache
+        let mut vm = VM::new(&program);
+        vm.set_extensions(ext.clone());
+        vm.predecode();
+        assert_eq!(run(&mut vm, &[7, 2]), Ok(vec![1, 3]));
+        let cache = vm.decode_cache().unwrap().clone();
+        let mut vm = VM::new(&program);
+        vm.register(Spec { params: &[In, Out], ..MOD }, |_| Ok(())).unwrap();
+        vm.share_decoded(&cache);
+        assert_eq!(run(&mut vm, &[7, 2]), Err(VmError::InvalidOpcode { pc: 4, op_code: 10 }));
+        assert_eq!(vm.pc, 4);
+    }
+}
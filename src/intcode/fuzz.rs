@@ -0,0 +1,492 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::num::ParseIntError;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::aot::Compiled;
+use super::decode::DecodeCache;
+use super::symbolic::{End, Symbolic};
+use super::{Mode, Op, Status, VM, VmError, Word};
+
+// splitmix64, which is plenty for generating programs and needs no crate
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in 0..n, n > 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // uniform in lo..=hi
+    pub fn range(&mut self, lo: isize, hi: isize) -> isize {
+        lo + self.below((hi - lo + 1) as usize) as isize
+    }
+}
+
+// a program and the inputs it is given up front
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Case {
+    pub program: Vec<isize>,
+    pub inputs: Vec<isize>,
+}
+
+fn join(words: &[isize]) -> String {
+    words.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",")
+}
+
+// the program on the first line and the inputs, possibly none, on the second
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", join(&self.program))?;
+        writeln!(f, "{}", join(&self.inputs))
+    }
+}
+
+impl FromStr for Case {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(str::trim);
+        let words = |line: Option<&str>| match line {
+            Some(line) if !line.is_empty() => super::parse(line),
+            _ => Ok(Vec::new()),
+        };
+        let program = words(lines.next())?;
+        let inputs = words(lines.next())?;
+        Ok(Self { program, inputs })
+    }
+}
+
+impl Case {
+    // writes the case into dir under a name derived from its contents, so
+    // saving the same failure twice leaves one file
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        let mut h = DefaultHasher::new();
+        self.hash(&mut h);
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{:016x}.txt", h.finish()));
+        fs::write(&path, self.to_string())?;
+        Ok(path)
+    }
+
+    // every case saved in dir, by file name
+    pub fn load_all(dir: &Path) -> io::Result<Vec<(PathBuf, Case)>> {
+        let mut cases = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "txt") {
+                let case = fs::read_to_string(&path)?.parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+                cases.push((path, case));
+            }
+        }
+        cases.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(cases)
+    }
+}
+
+// words of scratch data after the generated code
+const DATA: usize = 16;
+
+fn random_op(rng: &mut Rng) -> Op {
+    const CODES: [isize; 9] = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+    if rng.below(40) == 0 {
+        return Op::Halt;
+    }
+    let code = CODES[rng.below(CODES.len())];
+    let mut modes = vec![Mode::Ptr; Op::arity(code).unwrap()];
+    for m in modes.iter_mut() {
+        *m = [Mode::Ptr, Mode::Imm, Mode::Rel][rng.below(3)];
+    }
+    let mut op = Op::new(code, &modes).unwrap();
+    // never an immediate destination
+    while !op.is_valid() {
+        *modes.last_mut().unwrap() = [Mode::Ptr, Mode::Rel][rng.below(2)];
+        op = Op::new(code, &modes).unwrap();
+    }
+    op
+}
+
+// a program of the given number of instructions plus a final halt, followed
+// by some data. Operands point anywhere in the image, including at code, and
+// immediate jumps land on instruction boundaries.
+pub fn generate(rng: &mut Rng, instructions: usize) -> Case {
+    let ops: Vec<Op> = (0..instructions).map(|_| random_op(rng))
+        .chain(std::iter::once(Op::Halt))
+        .collect();
+    let starts: Vec<usize> = ops.iter()
+        .scan(0, |addr, op| {
+            let start = *addr;
+            *addr += op.size();
+            Some(start)
+        })
+        .collect();
+    let len = starts.last().unwrap() + 1 + DATA;
+
+    let mut program = Vec::with_capacity(len);
+    for op in &ops {
+        program.push(op.encode());
+        for (i, m) in op.modes().into_iter().enumerate() {
+            let word = match (op, m) {
+                (Op::Jump(_, _, _), Mode::Imm) if i == 1 => starts[rng.below(starts.len())] as isize,
+                (Op::Rebase(_), Mode::Imm) => rng.range(-4, 8),
+                (_, Mode::Imm) => rng.range(-20, 20),
+                (_, Mode::Ptr) => rng.below(len) as isize,
+                (_, Mode::Rel) => rng.range(-4, 12),
+            };
+            program.push(word);
+        }
+    }
+    program.extend((0..DATA).map(|_| rng.range(-20, 20)));
+    let inputs = (0..rng.below(5)).map(|_| rng.range(-20, 20)).collect();
+    Case { program, inputs }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Engine {
+    Plain,
+    Predecoded,
+    // a decode cache built from the image before the VM starts
+    Shared,
+    // i128 words
+    Wide,
+    // the symbolic executor with every value known
+    Symbolic,
+    // closures from aot, falling back to the interpreter
+    Compiled,
+}
+
+pub const ENGINES: [Engine; 6] = [
+    Engine::Plain,
+    Engine::Predecoded,
+    Engine::Shared,
+    Engine::Wide,
+    Engine::Symbolic,
+    Engine::Compiled,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Halted,
+    NeedsInput,
+    StepLimit,
+    Error(VmError),
+    Unsupported(&'static str),
+    Panic(String),
+}
+
+// everything observable once an engine stops
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub outputs: Vec<isize>,
+    pub stop: Stop,
+    pub pc: usize,
+    // trailing zeros trimmed, since engines grow memory differently
+    pub mem: Vec<isize>,
+}
+
+fn trimmed(mut mem: Vec<isize>) -> Vec<isize> {
+    while mem.last() == Some(&0) {
+        mem.pop();
+    }
+    mem
+}
+
+// values that don't fit are only reachable after isize has overflowed,
+// where the engines are not compared
+fn narrow<W: Word>(x: &W) -> isize {
+    x.to_isize().unwrap_or(isize::MAX)
+}
+
+fn run_vm<W: Word>(mut vm: VM<W>, inputs: &[isize], max_steps: usize) -> Outcome {
+    for x in inputs {
+        vm.push_input(W::from_isize(*x));
+    }
+    let mut outputs = Vec::new();
+    let mut steps = 0;
+    let stop = loop {
+        if steps >= max_steps {
+            break Stop::StepLimit;
+        }
+        steps += 1;
+        match vm.step() {
+            Ok(Status::Running) | Ok(Status::Watch(_)) => (),
+            Ok(Status::Output(x)) => outputs.push(narrow(&x)),
+            Ok(Status::NeedsInput) => break Stop::NeedsInput,
+            Ok(Status::Halted) => break Stop::Halted,
+            Err(e) => break Stop::Error(e),
+        }
+    };
+    let mem = trimmed(vm.mem.to_vec().iter().map(narrow).collect());
+    Outcome { outputs, stop, pc: vm.pc, mem }
+}
+
+// as run_vm, with translated code running wherever it can
+fn run_compiled(case: &Case, max_steps: usize) -> Outcome {
+    let compiled = Compiled::new(&case.program);
+    let mut vm = VM::new(&case.program);
+    for x in &case.inputs {
+        vm.push_input(*x);
+    }
+    let mut outputs = Vec::new();
+    let mut left = max_steps;
+    let stop = loop {
+        if let Some(x) = compiled.enter_for(&mut vm, &mut left) {
+            outputs.push(x);
+            continue;
+        }
+        if left == 0 {
+            break Stop::StepLimit;
+        }
+        left -= 1;
+        match vm.step() {
+            Ok(Status::Running) | Ok(Status::Watch(_)) => (),
+            Ok(Status::Output(x)) => outputs.push(x),
+            Ok(Status::NeedsInput) => break Stop::NeedsInput,
+            Ok(Status::Halted) => break Stop::Halted,
+            Err(e) => break Stop::Error(e),
+        }
+    };
+    Outcome { outputs, stop, pc: vm.pc, mem: trimmed(vm.mem.to_vec()) }
+}
+
+fn run_symbolic(case: &Case, max_steps: usize) -> Outcome {
+    let mut s = Symbolic::new(&case.program);
+    for x in &case.inputs {
+        s.push_input(*x);
+    }
+    let path = s.run(max_steps).remove(0);
+    // a value that is not constant only comes from overflow
+    let value = |e: &super::symbolic::Sym| e.value().unwrap_or(isize::MAX);
+    let stop = match path.end {
+        End::Halted => Stop::Halted,
+        End::NeedsInput => Stop::NeedsInput,
+        End::StepLimit | End::PathLimit => Stop::StepLimit,
+        End::Error(e) => Stop::Error(e),
+        End::Unsupported { what, .. } => Stop::Unsupported(what),
+    };
+    Outcome {
+        outputs: path.outputs.iter().map(value).collect(),
+        stop,
+        pc: path.pc,
+        mem: trimmed(path.mem.to_vec().iter().map(value).collect()),
+    }
+}
+
+// runs the case on one engine for at most max_steps instructions, turning a
+// panic into Stop::Panic
+pub fn run(engine: Engine, case: &Case, max_steps: usize) -> Outcome {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| match engine {
+        Engine::Plain => run_vm(VM::new(&case.program), &case.inputs, max_steps),
+        Engine::Predecoded => {
+            let mut vm = VM::new(&case.program);
+            vm.predecode();
+            run_vm(vm, &case.inputs, max_steps)
+        },
+        Engine::Shared => {
+            let mut vm = VM::new(&case.program);
+            vm.share_decoded(&DecodeCache::new(&case.program));
+            run_vm(vm, &case.inputs, max_steps)
+        },
+        Engine::Wide => {
+            let wide: Vec<i128> = case.program.iter().map(|x| *x as i128).collect();
+            run_vm(VM::from_words(&wide), &case.inputs, max_steps)
+        },
+        Engine::Symbolic => run_symbolic(case, max_steps),
+        Engine::Compiled => run_compiled(case, max_steps),
+    }));
+    result.unwrap_or_else(|e| {
+        let msg = e.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Outcome { outputs: Vec::new(), stop: Stop::Panic(msg), pc: 0, mem: Vec::new() }
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub engine: Engine,
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} differs from Plain: {:?} instead of {:?}", self.engine, self.actual, self.expected)
+    }
+}
+
+// runs the case on every engine and compares each with the plain VM. After
+// an isize overflow the wide and symbolic engines legitimately carry on, so
+// they're only compared when that didn't happen.
+pub fn check(case: &Case, max_steps: usize) -> Result<Outcome, Box<Mismatch>> {
+    let expected = run(Engine::Plain, case, max_steps);
+    if let Stop::Panic(_) = expected.stop {
+        return Err(Box::new(Mismatch { engine: Engine::Plain, expected: expected.clone(), actual: expected }));
+    }
+    let overflowed = matches!(expected.stop, Stop::Error(VmError::Overflow { .. }));
+    for &engine in &ENGINES[1..] {
+        if overflowed && (engine == Engine::Wide || engine == Engine::Symbolic) {
+            continue;
+        }
+        let actual = run(engine, case, max_steps);
+        if actual != expected {
+            return Err(Box::new(Mismatch { engine, expected, actual }));
+        }
+    }
+    Ok(expected)
+}
+
+// a smaller case on which the same engine still disagrees
+pub fn minimize(case: &Case, max_steps: usize) -> Case {
+    match check(case, max_steps) {
+        Err(m) => shrink(case, |c| matches!(check(c, max_steps), Err(n) if n.engine == m.engine)),
+        Ok(_) => case.clone(),
+    }
+}
+
+// drops inputs, removes runs of words and zeroes words for as long as any of
+// that keeps `fails` true
+pub fn shrink<F: Fn(&Case) -> bool>(case: &Case, fails: F) -> Case {
+    let mut best = case.clone();
+    loop {
+        let mut improved = false;
+        for i in (0..best.inputs.len()).rev() {
+            let mut c = best.clone();
+            c.inputs.remove(i);
+            if fails(&c) {
+                best = c;
+                improved = true;
+            }
+        }
+        let mut size = (best.program.len() / 2).max(1);
+        while size > 0 {
+            let mut start = 0;
+            while start + size <= best.program.len() {
+                let mut c = best.clone();
+                c.program.drain(start..start + size);
+                if fails(&c) {
+                    best = c;
+                    improved = true;
+                } else {
+                    start += size;
+                }
+            }
+            size /= 2;
+        }
+        for i in 0..best.program.len() {
+            if best.program[i] != 0 {
+                let mut c = best.clone();
+                c.program[i] = 0;
+                if fails(&c) {
+                    best = c;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            return best;
+        }
+    }
+}
+
+// checks `cases` generated programs, returning each failure minimized
+pub fn fuzz(seed: u64, cases: usize, max_steps: usize) -> Vec<(Case, Mismatch)> {
+    let mut rng = Rng::new(seed);
+    let mut failures = Vec::new();
+    for _ in 0..cases {
+        let instructions = 1 + rng.below(24);
+        let case = generate(&mut rng, instructions);
+        if check(&case, max_steps).is_err() {
+            let small = minimize(&case, max_steps);
+            if let Err(m) = check(&small, max_steps) {
+                failures.push((small, *m));
+            }
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 2_000;
+
+    #[test]
+    fn generated_programs_decode() {
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            let case = generate(&mut rng, 10);
+            let mut pc = 0;
+            for _ in 0..11 {
+                let op = Op::from(case.program[pc]).unwrap();
+                assert!(op.is_valid());
+                pc += op.size();
+            }
+            assert_eq!(Op::from(case.program[pc - 1]), Some(Op::Halt));
+            assert_eq!(case.program.len(), pc + DATA);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let case = Case { program: vec![1101, -1, 2, 0, 99], inputs: vec![] };
+        assert_eq!(case.to_string().parse::<Case>(), Ok(case.clone()));
+        let case = Case { inputs: vec![4, -5], ..case };
+        assert_eq!(case.to_string().parse::<Case>(), Ok(case));
+    }
+
+    #[test]
+    fn shrinks() {
+        // anything that still outputs 7 will do
+        let outputs_7 = |c: &Case| run(Engine::Plain, c, STEPS).outputs.contains(&7);
+        let case = Case {
+            program: vec![3, 13, 1001, 13, 2, 13, 4, 13, 1101, 5, 6, 14, 99, 0, 0],
+            inputs: vec![5, 9],
+        };
+        assert!(outputs_7(&case));
+        let small = shrink(&case, outputs_7);
+        // the halt and the constant store go, and so does the unused input
+        assert_eq!(small, Case { program: vec![3, 13, 1001, 13, 2, 13, 4, 13], inputs: vec![5] });
+        assert_eq!(minimize(&case, STEPS), case);
+    }
+
+    #[test]
+    fn random_programs() {
+        let failures = fuzz(2019, 300, STEPS);
+        assert!(failures.is_empty(), "{}", failures.iter()
+            .map(|(c, m)| format!("{}{}", c, m))
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+
+    #[test]
+    fn regressions() {
+        let cases = Case::load_all(Path::new("./input/fuzz")).unwrap();
+        assert!(!cases.is_empty());
+        for (path, case) in cases {
+            if let Err(m) = check(&case, STEPS) {
+                panic!("{}: {}", path.display(), m);
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::time::Instant;
+
+use super::{Op, Status, VM, VmError};
+use super::word::Word;
+
+// the clock is only read every so many steps
+const CLOCK_INTERVAL: usize = 1024;
+
+fn mix(mut x: u64) -> u64 {
+    // splitmix64 finalizer
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn cell<W: Word>(addr: usize, value: &W) -> u64 {
+    if value.is_zero() {
+        return 0;
+    }
+    // wider words would collide if truncated, so hash them whole
+    let bits = match value.to_isize() {
+        Some(x) => x as u64,
+        None => {
+            let mut h = DefaultHasher::new();
+            value.hash(&mut h);
+            h.finish()
+        },
+    };
+    mix(mix(addr as u64) ^ bits)
+}
+
+// Brent's cycle detection over the states seen since the last IO, so memory
+// use stays constant however long a program runs without IO; a loop is
+// caught within about twice its length of entering it. Memory is hashed
+// incrementally by xoring in one term per non-zero cell, so each write
+// costs two hashes instead of rehashing everything
+#[derive(Clone, Debug)]
+pub struct LoopCheck {
+    mem_hash: u64,
+    // the state at the last checkpoint, and steps since then
+    saved: Option<u64>,
+    steps: u64,
+    // steps before the next checkpoint, doubling each time
+    power: u64,
+}
+
+impl LoopCheck {
+    pub fn new<W: Word>(vm: &VM<W>) -> Self {
+        let mut mem_hash = 0;
+        for addr in 0..vm.mem.len() {
+            mem_hash ^= cell(addr, &vm.mem[addr]);
+        }
+        for (addr, value) in vm.mem.sparse() {
+            mem_hash ^= cell(addr, &value);
+        }
+        Self { mem_hash, saved: None, steps: 0, power: 1 }
+    }
+
+    #[inline(always)]
+    pub fn write<W: Word>(&mut self, addr: usize, old: &W, new: &W) {
+        self.mem_hash ^= cell(addr, old) ^ cell(addr, new);
+    }
+
+    pub fn clear(&mut self) {
+        self.saved = None;
+        self.steps = 0;
+        self.power = 1;
+    }
+
+    // false once this exact state has come round again with no IO in between
+    pub fn visit(&mut self, pc: usize, base: isize) -> bool {
+        let state = mix(self.mem_hash ^ mix(pc as u64)) ^ mix(base as u64 ^ 0x9e3779b97f4a7c15);
+        if self.saved == Some(state) {
+            return false;
+        }
+        self.steps += 1;
+        if self.saved.is_none() || self.steps == self.power {
+            self.saved = Some(state);
+            self.steps = 0;
+            self.power *= 2;
+        }
+        true
+    }
+}
+
+impl<W: Word> VM<W> {
+    // with loop detection on, returning to an identical pc, relative base
+    // and memory without any IO in between fails with VmError::Loop; writes
+    // straight to vm.mem skip the memory hash and can make a changed state
+    // look like a repeat, so call this again after making them
+    pub fn detect_loops(&mut self, enable: bool) {
+        self.loops = if enable { Some(LoopCheck::new(self)) } else { None };
+    }
+
+    // called before op runs, so a loop leaves the pc on the instruction
+    // that would repeat it
+    pub(super) fn check_loop(&mut self, op: Op) -> Result<(), VmError> {
+        if let Some(l) = &mut self.loops {
+            match op {
+                // callbacks may keep state of their own, so count as IO
+                Op::Read(_) | Op::Write(_) | Op::Ext(_, _) => l.clear(),
+                _ => {
+                    if !l.visit(self.pc, self.base) {
+                        return Err(VmError::Loop { pc: self.pc });
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // memory changed behind the VM's back, e.g. by a restore
+    pub(super) fn reset_loops(&mut self) {
+        if self.loops.is_some() {
+            self.loops = Some(LoopCheck::new(self));
+        }
+    }
+
+    // like run, but fails with StepLimit after max_steps instructions
+    pub fn run_for(&mut self, max_steps: usize) -> Result<Status<W>, VmError> {
+        for _ in 0..max_steps {
+            match self.step()? {
+                Status::Running => (),
+                status => return Ok(status),
+            }
+        }
+        Err(VmError::StepLimit { pc: self.pc, steps: max_steps })
+    }
+
+    // like run, but fails with Timeout once the deadline has passed
+    pub fn run_until(&mut self, deadline: Instant) -> Result<Status<W>, VmError> {
+        loop {
+            if Instant::now() >= deadline {
+                return Err(VmError::Timeout { pc: self.pc });
+            }
+            match self.run_for(CLOCK_INTERVAL) {
+                Err(VmError::StepLimit { .. }) => (),
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use std::time::Duration;
+
+    fn spin() -> Vec<isize> {
+        assemble("
+            loop:   add [n], 1, [n]
+                    jnz 1, loop
+            n:      data 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut vm = VM::new(&spin());
+        assert_eq!(vm.run_for(10), Err(VmError::StepLimit { pc: 0, steps: 10 }));
+        assert_eq!(vm.mem[7], 5);
+
+        let mut vm = VM::new(&[1101, 1, 1, 5, 99, 0]);
+        assert_eq!(vm.run_for(2), Ok(Status::Halted));
+        let mut vm = VM::new(&[1101, 1, 1, 5, 99, 0]);
+        assert!(vm.run_for(1).is_err());
+    }
+
+    #[test]
+    fn timeout() {
+        let mut vm = VM::new(&spin());
+        let start = Instant::now();
+        let result = vm.run_until(start + Duration::from_millis(20));
+        assert!(matches!(result, Err(VmError::Timeout { .. })));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let mut vm = VM::new(&[99]);
+        assert_eq!(vm.run_until(start + Duration::from_secs(60)), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn loops() {
+        // counting never repeats a state, so it isn't flagged
+        let mut vm = VM::new(&spin());
+        vm.detect_loops(true);
+        assert!(matches!(vm.run_for(1000), Err(VmError::StepLimit { .. })));
+
+        let prog = assemble("
+                    read [n]
+            loop:   equal [n], 0, [t]
+                    jnz 1, loop
+            n:      data 0
+            t:      data 0
+        ").unwrap();
+        let mut vm = VM::new(&prog);
+        vm.push_input(3);
+        vm.detect_loops(true);
+        assert_eq!(vm.run_for(1000), Err(VmError::Loop { pc: 2 }));
+        assert_eq!(VmError::Loop { pc: 2 }.to_string(), "infinite loop at 2");
+        // the repeating instruction hasn't run, so stepping fails the same way
+        assert_eq!(vm.pc, 2);
+        assert_eq!(vm.step(), Err(VmError::Loop { pc: 2 }));
+        assert_eq!(vm.pc, 2);
+
+        // polling for input is not a loop
+        let prog = assemble("
+            loop:   read [n]
+                    jnz 1, loop
+            n:      data 0
+        ").unwrap();
+        let mut vm = VM::new(&prog);
+        vm.detect_loops(true);
+        for _ in 0..10 {
+            vm.push_input(1);
+        }
+        assert_eq!(vm.run_for(1000), Ok(Status::NeedsInput));
+
+        // a long cycle is still caught, without remembering every state
+        let prog = assemble("
+            loop:   add [n], 1, [n]
+                    equal [n], 5000, [t]
+                    jz [t], loop
+                    add 0, 0, [n]
+                    jz 0, loop
+            n:      data 0
+            t:      data 0
+        ").unwrap();
+        let mut vm = VM::new(&prog);
+        vm.detect_loops(true);
+        match vm.run_for(100_000) {
+            Err(VmError::Loop { pc }) => assert_eq!(vm.pc, pc),
+            result => panic!("expected a loop, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn restore_rehashes() {
+        let prog = assemble("
+            loop:   add [n], 1, [n]
+                    jz 0, loop
+            n:      data 0
+        ").unwrap();
+        let mut vm = VM::new(&prog);
+        vm.detect_loops(true);
+        let snap = vm.snapshot();
+        vm.run_for(10).unwrap_err();
+        vm.restore(&snap);
+        assert!(matches!(vm.run_for(10), Err(VmError::StepLimit { .. })));
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+// addresses below this grow the dense image; anything higher is kept in a
+// sparse map so a single far write doesn't allocate gigabytes of zeroes
+const DENSE_LIMIT: usize = 1 << 20;
+
+// the dense image is split into shared pages, so cloning memory is cheap and
+// a page is only copied the first time a clone writes to it
+const PAGE: usize = 256;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Memory<W = isize> {
+    pages: Vec<Arc<[W; PAGE]>>,
+    len: usize,
+    sparse: HashMap<usize, W>,
+    // what reads past the end point at
+    zero: W,
+}
+
+fn page<W: Clone + Default>(chunk: &[W]) -> Arc<[W; PAGE]> {
+    Arc::new(std::array::from_fn(|i| chunk.get(i).cloned().unwrap_or_default()))
+}
+
+impl<W: Clone + Default> Memory<W> {
+    pub fn new(image: &[W]) -> Self {
+        Self {
+            pages: image.chunks(PAGE).map(page).collect(),
+            len: image.len(),
+            sparse: HashMap::new(),
+            zero: W::default(),
+        }
+    }
+
+    pub fn get(&self, addr: usize) -> W {
+        self[addr].clone()
+    }
+
+    pub fn set(&mut self, addr: usize, value: W) {
+        self[addr] = value;
+    }
+
+    // one past the highest densely stored address
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.sparse.is_empty()
+    }
+
+    pub fn to_vec(&self) -> Vec<W> {
+        (0..self.len).map(|addr| self.get(addr)).collect()
+    }
+
+    // entries beyond the dense image, in no particular order
+    pub fn sparse(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        self.sparse.iter().map(|(addr, x)| (*addr, x.clone()))
+    }
+
+    // number of pages this memory shares with another, for checking that
+    // clones haven't been copied
+    pub fn shared_pages(&self, other: &Memory<W>) -> usize {
+        self.pages.iter()
+            .zip(&other.pages)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+impl<W> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, addr: usize) -> &W {
+        if addr < self.len {
+            &self.pages[addr / PAGE][addr % PAGE]
+        } else {
+            self.sparse.get(&addr).unwrap_or(&self.zero)
+        }
+    }
+}
+
+impl<W: Clone + Default> IndexMut<usize> for Memory<W> {
+    fn index_mut(&mut self, addr: usize) -> &mut W {
+        if addr >= DENSE_LIMIT {
+            return self.sparse.entry(addr).or_default();
+        }
+        if addr >= self.len {
+            while self.pages.len() <= addr / PAGE {
+                self.pages.push(page(&[]));
+            }
+            self.len = addr + 1;
+        }
+        &mut Arc::make_mut(&mut self.pages[addr / PAGE])[addr % PAGE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_past_image_are_zero() {
+        let mem = Memory::new(&[1, 2, 3]);
+        assert_eq!(mem[2], 3);
+        assert_eq!(mem[3], 0);
+        assert_eq!(mem[usize::MAX], 0);
+        assert_eq!(mem.len(), 3);
+    }
+
+    #[test]
+    fn writes_zero_extend() {
+        let mut mem = Memory::new(&[1, 2, 3]);
+        mem[10] = 7;
+        assert_eq!(mem.len(), 11);
+        assert_eq!(mem.to_vec(), vec![1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 7]);
+
+        mem[1000] = 1;
+        assert_eq!(mem.len(), 1001);
+        assert_eq!(mem[999], 0);
+    }
+
+    #[test]
+    fn far_writes_are_sparse() {
+        let mut mem = Memory::new(&[1, 2, 3]);
+        mem.set(1 << 40, 42);
+        assert_eq!(mem.get(1 << 40), 42);
+        assert_eq!(mem.get((1 << 40) + 1), 0);
+        assert_eq!(mem.len(), 3);
+        assert_eq!(mem.sparse().collect::<Vec<_>>(), vec![(1 << 40, 42)]);
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let image: Vec<isize> = (0..1000).collect();
+        let a = Memory::new(&image);
+        let mut b = a.clone();
+        assert_eq!(a.shared_pages(&b), 4);
+
+        b[300] = -1;
+        assert_eq!(a.shared_pages(&b), 3);
+        assert_eq!(a[300], 300);
+        assert_eq!(b[300], -1);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn wide_words() {
+        let mut mem: Memory<i128> = Memory::new(&[1 << 100]);
+        mem[5000] = -(1 << 90);
+        assert_eq!(mem.get(0), 1 << 100);
+        assert_eq!(mem[5000], -(1 << 90));
+        assert_eq!(mem[4999], 0);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+
+use super::pipeline::NodeError;
+use super::{Status, VM};
+
+pub const NAT: isize = 255;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub from: isize,
+    pub to: isize,
+    pub x: isize,
+    pub y: isize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tick {
+    // everything sent by the computers during the tick, in order
+    pub sent: Vec<Packet>,
+    pub idle: bool,
+    // what the NAT sent to address 0 because the network was idle
+    pub nat: Option<Packet>,
+}
+
+// computers with addresses 0..n exchanging (dest, x, y) packets; each tick
+// every computer in address order gets one packet, or -1 if its queue is
+// empty, and runs until it asks for input again
+#[derive(Debug)]
+pub struct Network {
+    vms: Vec<VM>,
+    halted: Vec<bool>,
+    queues: Vec<VecDeque<(isize, isize)>>,
+    // output of a packet that hasn't been fully written yet
+    partial: Vec<Vec<isize>>,
+    // the last packet sent to the NAT
+    pub nat: Option<Packet>,
+    pub ticks: usize,
+}
+
+impl Network {
+    // every computer gets its address as the first input
+    pub fn new(program: &[isize], size: usize) -> Self {
+        let vms = (0..size).map(|addr| {
+            let mut vm = VM::new(program);
+            vm.push_input(addr as isize);
+            vm
+        }).collect();
+        Self {
+            vms,
+            halted: vec![false; size],
+            queues: vec![VecDeque::new(); size],
+            partial: vec![Vec::new(); size],
+            nat: None,
+            ticks: 0,
+        }
+    }
+
+    pub fn send(&mut self, to: usize, x: isize, y: isize) {
+        self.queues[to].push_back((x, y));
+    }
+
+    pub fn tick(&mut self) -> Result<Tick, NodeError> {
+        let mut tick = Tick::default();
+        let mut received = false;
+
+        for node in 0..self.vms.len() {
+            if self.halted[node] {
+                continue;
+            }
+            let vm = &mut self.vms[node];
+            match self.queues[node].pop_front() {
+                Some((x, y)) => {
+                    received = true;
+                    vm.push_input(x);
+                    vm.push_input(y);
+                },
+                None => vm.push_input(-1),
+            }
+
+            loop {
+                match vm.run().map_err(|error| NodeError { node, error })? {
+                    Status::Output(v) => {
+                        self.partial[node].push(v);
+                        if self.partial[node].len() == 3 {
+                            let p = &self.partial[node];
+                            tick.sent.push(Packet { from: node as isize, to: p[0], x: p[1], y: p[2] });
+                            self.partial[node].clear();
+                        }
+                    },
+                    Status::Halted => {
+                        self.halted[node] = true;
+                        break;
+                    },
+                    Status::NeedsInput => break,
+                    Status::Running | Status::Watch(_) => (),
+                }
+            }
+        }
+
+        for p in &tick.sent {
+            if p.to == NAT {
+                self.nat = Some(*p);
+            } else if p.to >= 0 && (p.to as usize) < self.queues.len() {
+                self.queues[p.to as usize].push_back((p.x, p.y));
+            }
+        }
+
+        tick.idle = !received && tick.sent.is_empty() && self.queues.iter().all(|q| q.is_empty());
+        if tick.idle {
+            if let Some(p) = self.nat {
+                self.send(0, p.x, p.y);
+                tick.nat = Some(Packet { from: NAT, to: 0, x: p.x, y: p.y });
+            }
+        }
+        self.ticks += 1;
+        Ok(tick)
+    }
+
+    // the first packet addressed to the NAT, if one is sent within max_ticks
+    pub fn first_to_nat(&mut self, max_ticks: usize) -> Result<Option<Packet>, NodeError> {
+        for _ in 0..max_ticks {
+            let tick = self.tick()?;
+            if let Some(p) = tick.sent.iter().find(|p| p.to == NAT) {
+                return Ok(Some(*p));
+            }
+        }
+        Ok(None)
+    }
+
+    // the first y value the NAT delivers twice in a row
+    pub fn first_repeated_nat_y(&mut self, max_ticks: usize) -> Result<Option<isize>, NodeError> {
+        let mut last = None;
+        for _ in 0..max_ticks {
+            if let Some(p) = self.tick()?.nat {
+                if last == Some(p.y) {
+                    return Ok(Some(p.y));
+                }
+                last = Some(p.y);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // node 0 starts a packet around the ring; each hop bumps x and the last
+    // node hands it to the NAT
+    fn ring(size: usize) -> Vec<isize> {
+        assemble(&format!("
+                    read [addr]
+                    jnz [addr], loop
+                    write 1
+                    write 0
+                    write 100
+            loop:   read [x]
+                    equal [x], -1, [t]
+                    jnz [t], loop
+                    read [y]
+                    add [addr], 1, [dst]
+                    add [x], 1, [x]
+                    equal [dst], {}, [t]
+                    jz [t], send
+                    add {}, 0, [dst]
+            send:   write [dst]
+                    write [x]
+                    write [y]
+                    jz 0, loop
+            addr:   data 0
+            x:      data 0
+            y:      data 0
+            dst:    data 0
+            t:      data 0
+        ", size, NAT)).unwrap()
+    }
+
+    #[test]
+    fn packets_reach_nat() {
+        let mut net = Network::new(&ring(50), 50);
+        let first = net.first_to_nat(1000).unwrap().unwrap();
+        assert_eq!(first, Packet { from: 49, to: NAT, x: 49, y: 100 });
+        assert_eq!(net.nat, Some(first));
+    }
+
+    #[test]
+    fn nat_restarts_idle_network() {
+        let mut net = Network::new(&ring(5), 5);
+        let mut deliveries = Vec::new();
+        let mut idle = 0;
+        for _ in 0..40 {
+            let tick = net.tick().unwrap();
+            if tick.idle {
+                idle += 1;
+            }
+            deliveries.extend(tick.nat);
+        }
+        assert!(idle >= 3);
+        assert_eq!(&deliveries[..3], &[
+            Packet { from: NAT, to: 0, x: 4, y: 100 },
+            Packet { from: NAT, to: 0, x: 9, y: 100 },
+            Packet { from: NAT, to: 0, x: 14, y: 100 },
+        ]);
+
+        let mut net = Network::new(&ring(50), 50);
+        assert_eq!(net.first_repeated_nat_y(1000), Ok(Some(100)));
+    }
+
+    #[test]
+    fn deterministic() {
+        let run = || {
+            let mut net = Network::new(&ring(10), 10);
+            (0..30).map(|_| net.tick().unwrap()).collect::<Vec<Tick>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn stalls_and_errors() {
+        let mut net = Network::new(&[3, 0, 99], 3);
+        assert_eq!(net.first_to_nat(10), Ok(None));
+
+        let mut net = Network::new(&[3, 0, 3, 0, 98], 2);
+        let err = net.tick().unwrap_err();
+        assert_eq!(err.node, 0);
+    }
+}
@@ -0,0 +1,266 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{Status, VM, VmError};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NodeError {
+    pub node: usize,
+    pub error: VmError,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {}: {}", self.node, self.error)
+    }
+}
+
+impl Error for NodeError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Outcome {
+    // every value each node wrote, whether or not it was routed anywhere
+    pub outputs: Vec<Vec<isize>>,
+    // nodes in the order they halted
+    pub halted: Vec<usize>,
+    // nodes left waiting for input when nothing else could run
+    pub blocked: Vec<usize>,
+}
+
+impl Outcome {
+    pub fn last_output(&self, node: usize) -> Option<isize> {
+        self.outputs.get(node).and_then(|o| o.last().copied())
+    }
+}
+
+// VMs wired output to input in any topology, run round robin on one thread
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    vms: Vec<VM>,
+    links: Vec<Vec<usize>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a straight chain of copies of one program, each given its phase as
+    // the first input; with feedback the last node feeds the first
+    pub fn chain(program: &[isize], phases: &[isize], feedback: bool) -> Self {
+        let mut p = Self::new();
+        for phase in phases {
+            let node = p.add(VM::new(program));
+            p.input(node, *phase);
+        }
+        for node in 1..phases.len() {
+            p.connect(node - 1, node);
+        }
+        if feedback && !phases.is_empty() {
+            p.connect(phases.len() - 1, 0);
+        }
+        p
+    }
+
+    pub fn add(&mut self, vm: VM) -> usize {
+        self.vms.push(vm);
+        self.links.push(Vec::new());
+        self.vms.len() - 1
+    }
+
+    // every output of `from` is queued as input to `to`
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.links[from].push(to);
+    }
+
+    pub fn input(&mut self, node: usize, value: isize) {
+        self.vms[node].push_input(value);
+    }
+
+    pub fn vm(&self, node: usize) -> &VM {
+        &self.vms[node]
+    }
+
+    // runs until every node has halted or all that remain are waiting on
+    // input nobody will send
+    pub fn run(&mut self) -> Result<Outcome, NodeError> {
+        self.run_for(usize::MAX)
+    }
+
+    // like run, but fails with StepLimit on whichever node is running once
+    // the nodes have taken max_steps steps between them
+    pub fn run_for(&mut self, max_steps: usize) -> Result<Outcome, NodeError> {
+        let mut outcome = Outcome {
+            outputs: vec![Vec::new(); self.vms.len()],
+            ..Outcome::default()
+        };
+        let mut done = vec![false; self.vms.len()];
+        let mut steps = 0;
+
+        loop {
+            let mut progress = false;
+            for (node, finished) in done.iter_mut().enumerate() {
+                if *finished {
+                    continue;
+                }
+                loop {
+                    if steps == max_steps {
+                        let error = VmError::StepLimit { pc: self.vms[node].pc, steps };
+                        return Err(NodeError { node, error });
+                    }
+                    steps += 1;
+                    let status = self.vms[node].step().map_err(|error| NodeError { node, error })?;
+                    match status {
+                        Status::Output(x) => {
+                            progress = true;
+                            outcome.outputs[node].push(x);
+                            for i in 0..self.links[node].len() {
+                                let to = self.links[node][i];
+                                self.vms[to].push_input(x);
+                            }
+                        },
+                        Status::Halted => {
+                            progress = true;
+                            *finished = true;
+                            outcome.halted.push(node);
+                            break;
+                        },
+                        Status::NeedsInput => break,
+                        Status::Running | Status::Watch(_) => (),
+                    }
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+
+        outcome.blocked = (0..self.vms.len()).filter(|n| !done[*n]).collect();
+        Ok(outcome)
+    }
+}
+
+// feeds `signal` into the first amplifier and returns the last value the
+// final amplifier produces
+pub fn amplify(program: &[isize], phases: &[isize], signal: isize, feedback: bool) -> Result<Option<isize>, NodeError> {
+    let mut p = Pipeline::chain(program, phases, feedback);
+    if phases.is_empty() {
+        return Ok(None);
+    }
+    p.input(0, signal);
+    let outcome = p.run()?;
+    Ok(outcome.last_output(phases.len() - 1))
+}
+
+// every ordering of values, in lexicographic order of positions
+pub fn permutations(values: &[isize]) -> Vec<Vec<isize>> {
+    if values.len() <= 1 {
+        return vec![values.to_vec()];
+    }
+    let mut result = Vec::new();
+    for i in 0..values.len() {
+        let mut rest = values.to_vec();
+        let first = rest.remove(i);
+        for mut p in permutations(&rest) {
+            p.insert(0, first);
+            result.push(p);
+        }
+    }
+    result
+}
+
+// the phase ordering giving the highest final signal
+pub fn best_phases(program: &[isize], phases: &[isize], feedback: bool) -> Result<Option<(isize, Vec<isize>)>, NodeError> {
+    let mut best: Option<(isize, Vec<isize>)> = None;
+    for p in permutations(phases) {
+        if let Some(signal) = amplify(program, &p, 0, feedback)? {
+            if best.as_ref().is_none_or(|(b, _)| signal > *b) {
+                best = Some((signal, p));
+            }
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain() {
+        let prog = vec![3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+        assert_eq!(amplify(&prog, &[4, 3, 2, 1, 0], 0, false), Ok(Some(43210)));
+        assert_eq!(best_phases(&prog, &[0, 1, 2, 3, 4], false), Ok(Some((43210, vec![4, 3, 2, 1, 0]))));
+
+        let prog = vec![
+            3, 23, 3, 24, 1002, 24, 10, 24, 1002, 23, -1, 23,
+            101, 5, 23, 23, 1, 24, 23, 23, 4, 23, 99, 0, 0,
+        ];
+        assert_eq!(best_phases(&prog, &[0, 1, 2, 3, 4], false), Ok(Some((54321, vec![0, 1, 2, 3, 4]))));
+    }
+
+    #[test]
+    fn feedback() {
+        let prog = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26,
+            27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        assert_eq!(amplify(&prog, &[9, 8, 7, 6, 5], 0, true), Ok(Some(139629729)));
+        assert_eq!(best_phases(&prog, &[5, 6, 7, 8, 9], true), Ok(Some((139629729, vec![9, 8, 7, 6, 5]))));
+
+        let mut p = Pipeline::chain(&prog, &[9, 8, 7, 6, 5], true);
+        p.input(0, 0);
+        let outcome = p.run().unwrap();
+        assert_eq!(outcome.halted, vec![0, 1, 2, 3, 4]);
+        assert!(outcome.blocked.is_empty());
+        assert_eq!(outcome.outputs[4].len(), 5);
+    }
+
+    #[test]
+    fn fan_out_and_deadlock() {
+        // echo once, double, and a node that waits forever
+        let echo = vec![3, 9, 4, 9, 99, 0, 0, 0, 0, 0];
+        let double = vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+        let mut p = Pipeline::new();
+        let a = p.add(VM::new(&echo));
+        let b = p.add(VM::new(&double));
+        let c = p.add(VM::new(&double));
+        let d = p.add(VM::new(&double));
+        p.connect(a, b);
+        p.connect(a, c);
+        p.input(a, 21);
+
+        let outcome = p.run().unwrap();
+        assert_eq!(outcome.outputs, vec![vec![21], vec![42], vec![42], vec![]]);
+        assert_eq!(outcome.halted, vec![a, b, c]);
+        assert_eq!(outcome.blocked, vec![d]);
+        assert_eq!(outcome.last_output(b), Some(42));
+    }
+
+    #[test]
+    fn errors() {
+        let mut p = Pipeline::new();
+        p.add(VM::new(&[99]));
+        p.add(VM::new(&[98]));
+        let err = p.run().unwrap_err();
+        assert_eq!(err, NodeError { node: 1, error: VmError::InvalidOpcode { pc: 0, op_code: 98 } });
+        assert_eq!(err.to_string(), "node 1: invalid op code 98 at 0");
+
+        // a node spinning without IO uses up the budget instead of hanging
+        let mut p = Pipeline::chain(&[3, 9, 4, 9, 99, 0, 0, 0, 0, 0], &[1, 2], false);
+        p.add(VM::new(&[1106, 0, 0]));
+        let err = p.run_for(1000).unwrap_err();
+        assert_eq!(err, NodeError { node: 2, error: VmError::StepLimit { pc: 0, steps: 1000 } });
+        assert_eq!(p.vm(1).mem[9], 2);
+    }
+
+    #[test]
+    fn permutations_of() {
+        assert_eq!(permutations(&[1, 2, 3]), vec![
+            vec![1, 2, 3], vec![1, 3, 2],
+            vec![2, 1, 3], vec![2, 3, 1],
+            vec![3, 1, 2], vec![3, 2, 1],
+        ]);
+        assert_eq!(permutations(&[0, 1, 2, 3, 4]).len(), 120);
+    }
+}
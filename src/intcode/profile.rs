@@ -0,0 +1,165 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disasm;
+use super::{Op, VM};
+use super::word::Word;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    // instructions executed
+    pub cycles: u64,
+    // executions per instruction address
+    pub counts: BTreeMap<usize, u64>,
+    // executions per mnemonic
+    pub ops: BTreeMap<&'static str, u64>,
+}
+
+impl Profile {
+    #[inline(always)]
+    pub(super) fn record(&mut self, pc: usize, op: Op) {
+        self.cycles += 1;
+        *self.counts.entry(pc).or_insert(0) += 1;
+        *self.ops.entry(op.mnemonic()).or_insert(0) += 1;
+    }
+
+    // the n most executed addresses, busiest first
+    pub fn hot(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut hot: Vec<(usize, u64)> = self.counts.iter().map(|(a, c)| (*a, *c)).collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(n);
+        hot
+    }
+
+    // statically reachable instructions that never ran
+    pub fn unreached(&self, program: &[isize]) -> Vec<usize> {
+        disasm::reachable(program).into_iter()
+            .filter(|a| !self.counts.contains_key(a))
+            .collect()
+    }
+
+    // the disassembly with an execution count, or `-` for instructions that
+    // never ran, in front of every line
+    pub fn coverage(&self, program: &[isize]) -> String {
+        let mut code: BTreeSet<usize> = disasm::reachable(program);
+        code.extend(self.counts.keys().filter(|a| **a < program.len()));
+
+        let mut s = String::new();
+        for line in disasm::disassemble_code(program, &code) {
+            let count = match (line.op, self.counts.get(&line.addr)) {
+                (None, _) => String::new(),
+                (Some(_), Some(c)) => c.to_string(),
+                (Some(_), None) => "-".to_string(),
+            };
+            writeln!(s, "{:>8}  {}", count, line).unwrap();
+        }
+        s
+    }
+
+    pub fn report(&self, program: &[isize]) -> String {
+        let mut s = String::new();
+        writeln!(s, "cycles: {}", self.cycles).unwrap();
+        writeln!(s, "\nhot addresses:").unwrap();
+        for (addr, count) in self.hot(10) {
+            writeln!(s, "{:>8}  {:04}", count, addr).unwrap();
+        }
+        writeln!(s, "\nopcodes:").unwrap();
+        let mut ops: Vec<(&str, u64)> = self.ops.iter().map(|(o, c)| (*o, *c)).collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (op, count) in ops {
+            writeln!(s, "{:>8}  {}", count, op).unwrap();
+        }
+        writeln!(s, "\ncoverage:").unwrap();
+        s + &self.coverage(program)
+    }
+
+    // address,count rows for loading into other tools
+    pub fn csv(&self) -> String {
+        let mut s = "address,count\n".to_string();
+        for (addr, count) in &self.counts {
+            writeln!(s, "{},{}", addr, count).unwrap();
+        }
+        s
+    }
+}
+
+impl<W: Word> VM<W> {
+    // starts counting instructions executed from here on
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::Status;
+
+    // unlike testing::countdown, the loop can be skipped and the last
+    // write is never reached, for the coverage reports
+    fn dead_code() -> Vec<isize> {
+        assemble("
+                    read [n]
+                    jz [n], skip
+            loop:   add [n], -1, [n]
+                    jnz [n], loop
+            skip:   write [n]
+                    halt
+                    write 999
+            n:      data 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn counts() {
+        let prog = dead_code();
+        let mut vm = VM::new(&prog);
+        vm.push_input(3);
+        vm.start_profile();
+        assert_eq!(vm.run(), Ok(Status::Output(0)));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+
+        let p = vm.take_profile().unwrap();
+        assert_eq!(p.cycles, 10);
+        assert_eq!(p.hot(2), vec![(5, 3), (9, 3)]);
+        assert_eq!(p.ops["add"], 3);
+        assert_eq!(p.ops["halt"], 1);
+        assert_eq!(p.unreached(&prog), Vec::<usize>::new());
+        assert!(p.csv().starts_with("address,count\n0,1\n2,1\n5,3\n"));
+    }
+
+    #[test]
+    fn coverage() {
+        let prog = dead_code();
+        let mut vm = VM::new(&prog);
+        vm.push_input(0);
+        vm.start_profile();
+        vm.run().unwrap();
+        vm.run().unwrap();
+
+        let p = vm.profile().unwrap();
+        assert_eq!(p.unreached(&prog), vec![5, 9]);
+        let lines: Vec<&str> = vec![
+            "       1  0000  3 17                      read [17]",
+            "       1  0002  1006 17 12                jz [17], 12",
+            "       -  0005  1001 17 -1 17             add [17], -1, [17]",
+            "       -  0009  1005 17 5                 jnz [17], 5",
+            "       1  0012  4 17                      write [17]",
+            "       1  0014  99                        halt",
+            "          0015  104 999 0                 data 104, 999, 0",
+        ];
+        assert_eq!(p.coverage(&prog), lines.join("\n") + "\n");
+        let report = p.report(&prog);
+        assert!(report.starts_with("cycles: 4\n\nhot addresses:\n       1  0000\n"));
+        assert!(report.contains("opcodes:\n       1  halt\n       1  jz\n"));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use super::{parse_words, Memory, VM};
+use super::word::Word;
+
+// everything needed to resume a VM; memory pages are shared with the VM
+// the snapshot was taken from until one of them writes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<W = isize> {
+    pub mem: Memory<W>,
+    pub pc: usize,
+    pub base: isize,
+    pub halt: bool,
+    pub input: VecDeque<W>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn join<W: Word>(values: impl Iterator<Item = W>) -> String {
+    values.map(|x| x.to_string()).collect::<Vec<String>>().join(",")
+}
+
+impl<W: Word> Snapshot<W> {
+    // one `key value` pair per line; memory is written in the same comma
+    // separated format as the puzzle inputs
+    pub fn save<T: Write>(&self, w: &mut T) -> io::Result<()> {
+        writeln!(w, "pc {}", self.pc)?;
+        writeln!(w, "base {}", self.base)?;
+        writeln!(w, "halt {}", self.halt)?;
+        writeln!(w, "input {}", join(self.input.iter().cloned()))?;
+        writeln!(w, "mem {}", join(self.mem.to_vec().into_iter()))?;
+        let mut sparse: Vec<(usize, W)> = self.mem.sparse().collect();
+        sparse.sort_unstable();
+        for (addr, x) in sparse {
+            writeln!(w, "at {} {}", addr, x)?;
+        }
+        Ok(())
+    }
+
+    pub fn load<R: Read>(r: R) -> io::Result<Self> {
+        let mut s = Self {
+            mem: Memory::default(),
+            pc: 0,
+            base: 0,
+            halt: false,
+            input: VecDeque::new(),
+        };
+        for line in BufReader::new(r).lines() {
+            let line = line?;
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => (line.as_str(), ""),
+            };
+            let words = || -> io::Result<Vec<W>> {
+                if value.is_empty() {
+                    Ok(Vec::new())
+                } else {
+                    parse_words(value).map_err(|_| invalid("bad number"))
+                }
+            };
+            match key {
+                "pc" => s.pc = usize::from_str(value).map_err(|_| invalid("bad pc"))?,
+                "base" => s.base = isize::from_str(value).map_err(|_| invalid("bad base"))?,
+                "halt" => s.halt = bool::from_str(value).map_err(|_| invalid("bad halt"))?,
+                "input" => s.input = words()?.into_iter().collect(),
+                "mem" => s.mem = Memory::new(&words()?),
+                "at" => {
+                    let mut parts = value.split(' ');
+                    let addr = parts.next().and_then(|x| usize::from_str(x).ok());
+                    let x = parts.next().and_then(|x| W::from_str(x).ok());
+                    match (addr, x) {
+                        (Some(addr), Some(x)) => s.mem[addr] = x,
+                        _ => return Err(invalid("bad sparse entry")),
+                    }
+                },
+                "" => (),
+                _ => return Err(invalid("unknown key")),
+            }
+        }
+        Ok(s)
+    }
+}
+
+impl<W: Word> VM<W> {
+    pub fn snapshot(&self) -> Snapshot<W> {
+        Snapshot {
+            mem: self.mem.clone(),
+            pc: self.pc,
+            base: self.base,
+            halt: self.halt,
+            input: self.input.clone(),
+        }
+    }
+
+    // IO channels, watchpoints and recording are left as they are
+    pub fn restore(&mut self, s: &Snapshot<W>) {
+        self.mem = s.mem.clone();
+        self.pc = s.pc;
+        self.base = s.base;
+        self.halt = s.halt;
+        self.input = s.input.clone();
+        self.reset_loops();
+    }
+
+    pub fn from_snapshot(s: &Snapshot<W>) -> Self {
+        let mut vm = Self::from_words(&[]);
+        vm.restore(s);
+        vm
+    }
+
+    // a copy with queued IO that runs independently, keeping the overflow
+    // policy, registered extensions, loop detection and the decode cache,
+    // which stays shared; IO channels, watchpoints, recording and profiling
+    // are left behind
+    pub fn fork(&self) -> Self {
+        let mut vm = Self::from_snapshot(&self.snapshot());
+        vm.overflow = self.overflow;
+        vm.extensions = self.extensions.clone();
+        vm.loops = self.loops.clone();
+        vm.decoded = self.decoded.clone();
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::testing;
+    use crate::intcode::{Status, VmError};
+
+    fn countdown() -> VM {
+        VM::new(&testing::countdown())
+    }
+
+    #[test]
+    fn restore() {
+        let mut vm = countdown();
+        vm.push_input(3);
+        vm.push_input(7);
+        assert_eq!(vm.run(), Ok(Status::Output(3)));
+        let snap = vm.snapshot();
+
+        assert_eq!(vm.run(), Ok(Status::Output(2)));
+        assert_eq!(vm.run(), Ok(Status::Output(1)));
+        vm.restore(&snap);
+        assert_eq!(vm.run(), Ok(Status::Output(2)));
+
+        let mut fork = vm.fork();
+        assert_eq!(fork.run(), Ok(Status::Output(1)));
+        assert_eq!(fork.run(), Ok(Status::Halted));
+        assert_eq!(vm.mem[12], 2);
+        assert_eq!(fork.snapshot().input, vec![7]);
+    }
+
+    #[test]
+    fn forks_share_memory() {
+        let mut image: Vec<isize> = vec![0; 2000];
+        image[..3].copy_from_slice(&[1101, 1, 1]);
+        image[3] = 1999;
+        image[4] = 99;
+        let vm = VM::new(&image);
+
+        let forks: Vec<VM> = (0..1000).map(|_| {
+            let mut f = vm.fork();
+            f.run().unwrap();
+            f
+        }).collect();
+        for f in &forks {
+            assert_eq!(f.mem[1999], 2);
+            // only the page holding 1999 was copied
+            assert_eq!(f.mem.shared_pages(&vm.mem), 7);
+        }
+    }
+
+    #[test]
+    fn forks_keep_settings() {
+        use crate::intcode::word::Overflow;
+
+        let mut vm = testing::with_neg("
+                    mul [big], [big], [x]
+                    neg [x], [x]
+                    write [x]
+                    halt
+            big:    data 3037000500
+            x:      data 0
+        ");
+        vm.overflow = Overflow::Wrapping;
+        vm.predecode();
+        vm.start_profile();
+        let expected = -3037000500isize.wrapping_mul(3037000500);
+        let mut fork = vm.fork();
+        assert_eq!(fork.decode_cache().unwrap().len(), vm.decode_cache().unwrap().len());
+        assert!(fork.profile().is_none());
+        assert_eq!(fork.run(), Ok(Status::Output(expected)));
+        assert_eq!(vm.run(), Ok(Status::Output(expected)));
+
+        let mut vm = VM::new(&[1106, 0, 0]);
+        vm.detect_loops(true);
+        assert_eq!(vm.fork().run_for(100), Err(VmError::Loop { pc: 0 }));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut vm = countdown();
+        vm.push_input(5);
+        vm.push_input(-6);
+        vm.run().unwrap();
+        vm.base = -4;
+        vm.mem[1 << 30] = 9;
+        let snap = vm.snapshot();
+
+        let mut file = Vec::new();
+        snap.save(&mut file).unwrap();
+        let text = String::from_utf8(file.clone()).unwrap();
+        assert_eq!(text, "\
+pc 4
+base -4
+halt false
+input -6
+mem 3,12,4,12,1001,12,-1,12,1005,12,2,99,5
+at 1073741824 9
+");
+        let loaded = Snapshot::load(&file[..]).unwrap();
+        assert_eq!(loaded, snap);
+
+        let mut vm = VM::from_snapshot(&loaded);
+        assert_eq!(vm.run(), Ok(Status::Output(4)));
+        assert!(Snapshot::<isize>::load(&b"pc x\n"[..]).is_err());
+    }
+}
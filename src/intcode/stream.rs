@@ -0,0 +1,490 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use super::word::Word;
+use super::{Status, VM, VmError};
+
+// the same shape as the futures crate's Stream and Sink, so adapting either
+// way is a few lines, without depending on it
+
+pub trait Stream {
+    type Item;
+
+    // Ready(None) once the stream has ended
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+}
+
+pub trait Sink<T> {
+    type Error;
+
+    // must be Ready(Ok) before each start_send
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>>;
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error>;
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>>;
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>>;
+}
+
+impl<S: Stream + Unpin + ?Sized> Stream for &mut S {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+}
+
+impl<T, S: Sink<T> + Unpin + ?Sized> Sink<T> for &mut S {
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut **self).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), S::Error> {
+        Pin::new(&mut **self).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
+
+// collects everything sent, and never fails
+impl<T: Unpin> Sink<T> for Vec<T> {
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Infallible> {
+        self.get_mut().push(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+// a stream that is always ready with the items of an iterator
+#[derive(Clone, Debug)]
+pub struct Iter<I> {
+    iter: I,
+}
+
+pub fn iter<I: IntoIterator>(i: I) -> Iter<I::IntoIter> {
+    Iter { iter: i.into_iter() }
+}
+
+impl<I: Iterator + Unpin> Stream for Iter<I> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.iter.next())
+    }
+}
+
+pub async fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+    poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+}
+
+// waits for room, sends and flushes
+pub async fn send<T, S: Sink<T> + Unpin>(sink: &mut S, item: T) -> Result<(), S::Error> {
+    poll_fn(|cx| Pin::new(&mut *sink).poll_ready(cx)).await?;
+    Pin::new(&mut *sink).start_send(item)?;
+    poll_fn(|cx| Pin::new(&mut *sink).poll_flush(cx)).await
+}
+
+pub async fn close<T, S: Sink<T> + Unpin>(sink: &mut S) -> Result<(), S::Error> {
+    poll_fn(|cx| Pin::new(&mut *sink).poll_close(cx)).await
+}
+
+// pending once, so other tasks get a turn
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }).await
+}
+
+// the other end of a channel is gone
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl Error for Closed {}
+
+#[derive(Debug)]
+struct Chan<T> {
+    queue: VecDeque<T>,
+    bound: usize,
+    sender_gone: bool,
+    receiver_gone: bool,
+    // whoever is waiting on the other end
+    send_waker: Option<Waker>,
+    recv_waker: Option<Waker>,
+}
+
+// single producer, single consumer; the receiver sees the end of the stream
+// once the sender is closed or dropped and the queue is drained
+#[derive(Debug)]
+pub struct Sender<T> {
+    chan: Arc<Mutex<Chan<T>>>,
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    chan: Arc<Mutex<Chan<T>>>,
+}
+
+// holds up to bound items before the sender has to wait
+pub fn channel<T>(bound: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(bound > 0, "a channel needs room for at least one item");
+    let chan = Arc::new(Mutex::new(Chan {
+        queue: VecDeque::new(),
+        bound,
+        sender_gone: false,
+        receiver_gone: false,
+        send_waker: None,
+        recv_waker: None,
+    }));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl<T> Sender<T> {
+    fn close(&self) {
+        let mut c = self.chan.lock().unwrap();
+        c.sender_gone = true;
+        if let Some(w) = c.recv_waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<T> Sink<T> for Sender<T> {
+    type Error = Closed;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Closed>> {
+        let mut c = self.chan.lock().unwrap();
+        if c.receiver_gone || c.sender_gone {
+            Poll::Ready(Err(Closed))
+        } else if c.queue.len() < c.bound {
+            Poll::Ready(Ok(()))
+        } else {
+            c.send_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Closed> {
+        let mut c = self.chan.lock().unwrap();
+        if c.receiver_gone || c.sender_gone {
+            return Err(Closed);
+        }
+        c.queue.push_back(item);
+        if let Some(w) = c.recv_waker.take() {
+            w.wake();
+        }
+        Ok(())
+    }
+
+    // anything sent is already visible to the receiver
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Closed>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> Receiver<T> {
+    // an item if one is queued, without waiting
+    pub fn try_next(&mut self) -> Option<T> {
+        let mut c = self.chan.lock().unwrap();
+        let item = c.queue.pop_front();
+        if item.is_some() {
+            if let Some(w) = c.send_waker.take() {
+                w.wake();
+            }
+        }
+        item
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut c = self.chan.lock().unwrap();
+        match c.queue.pop_front() {
+            Some(item) => {
+                if let Some(w) = c.send_waker.take() {
+                    w.wake();
+                }
+                Poll::Ready(Some(item))
+            },
+            None if c.sender_gone => Poll::Ready(None),
+            None => {
+                c.recv_waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut c = self.chan.lock().unwrap();
+        c.receiver_gone = true;
+        if let Some(w) = c.send_waker.take() {
+            w.wake();
+        }
+    }
+}
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// runs a future to completion on the current thread; a future that is never
+// woken blocks forever, e.g. VMs all waiting on each other
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+            return out;
+        }
+        thread::park();
+    }
+}
+
+// polls every unfinished future whenever any of them is woken, resolving to
+// their outputs in order once all are done
+pub struct JoinAll<F: Future> {
+    futs: Vec<Pin<Box<F>>>,
+    outputs: Vec<Option<F::Output>>,
+}
+
+pub fn join_all<I: IntoIterator>(futs: I) -> JoinAll<I::Item>
+where
+    I::Item: Future,
+{
+    let futs: Vec<_> = futs.into_iter().map(Box::pin).collect();
+    let outputs = futs.iter().map(|_| None).collect();
+    JoinAll { futs, outputs }
+}
+
+// the futures are pinned in their own boxes and outputs are never pinned
+impl<F: Future> Unpin for JoinAll<F> {}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Vec<F::Output>> {
+        let this = self.get_mut();
+        for (fut, out) in this.futs.iter_mut().zip(&mut this.outputs) {
+            if out.is_none() {
+                if let Poll::Ready(x) = fut.as_mut().poll(cx) {
+                    *out = Some(x);
+                }
+            }
+        }
+        if this.outputs.iter().all(Option::is_some) {
+            Poll::Ready(this.outputs.iter_mut().map(|o| o.take().unwrap()).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// instructions run between yields, so a VM that computes for a long time
+// without IO doesn't starve the tasks sharing its thread
+const SLICE: usize = 10_000;
+
+impl<W: Word> VM<W> {
+    // runs like run, but waits on the input stream when out of queued input
+    // and sends each output to the sink; the stream ending fails with
+    // InputClosed and a sink error with OutputClosed. Returns on halting or
+    // at a watchpoint. Channels from with_io still block the thread.
+    pub async fn run_async<I, O>(&mut self, mut input: I, mut output: O) -> Result<Status<W>, VmError>
+    where
+        I: Stream<Item = W> + Unpin,
+        O: Sink<W> + Unpin,
+    {
+        loop {
+            match self.run_for(SLICE) {
+                Ok(Status::Running) => (),
+                Ok(Status::Output(x)) => send(&mut output, x).await
+                    .map_err(|_| VmError::OutputClosed { pc: self.op_pc })?,
+                Ok(Status::NeedsInput) => match next(&mut input).await {
+                    Some(x) => self.push_input(x),
+                    None => return Err(VmError::InputClosed { pc: self.pc }),
+                },
+                Err(VmError::StepLimit { .. }) => yield_now().await,
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // outputs running sums of its inputs until it reads a 0
+    fn sums() -> Vec<isize> {
+        assemble("
+            loop:   read [x]
+                    jz [x], end
+                    add [sum], [x], [sum]
+                    write [sum]
+                    jnz 1, loop
+            end:    halt
+            x:      data 0
+            sum:    data 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn iter_to_vec() {
+        let mut vm = VM::new(&sums());
+        let mut out = Vec::new();
+        let status = block_on(vm.run_async(iter(vec![1, 2, 3, 0]), &mut out));
+        assert_eq!(status, Ok(Status::Halted));
+        assert_eq!(out, [1, 3, 6]);
+
+        let mut vm = VM::new(&sums());
+        let err = block_on(vm.run_async(iter(vec![1, 2]), Vec::new())).unwrap_err();
+        assert_eq!(err, VmError::InputClosed { pc: 0 });
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn channels() {
+        let (mut tx, mut rx) = channel(1);
+        let sender = async move {
+            for x in 0..5 {
+                send(&mut tx, x).await.unwrap();
+            }
+            drop(tx);
+            Vec::new()
+        };
+        let receiver = async move {
+            let mut got = Vec::new();
+            while let Some(x) = next(&mut rx).await {
+                got.push(x);
+            }
+            got
+        };
+        let tasks: Vec<Pin<Box<dyn Future<Output = Vec<isize>>>>> = vec![Box::pin(sender), Box::pin(receiver)];
+        assert_eq!(block_on(join_all(tasks)), [vec![], vec![0, 1, 2, 3, 4]]);
+
+        let (mut tx, rx) = channel(1);
+        drop(rx);
+        assert_eq!(block_on(send(&mut tx, 1)), Err(Closed));
+        let (mut tx, mut rx) = channel(2);
+        block_on(send(&mut tx, 1)).unwrap();
+        block_on(close(&mut tx)).unwrap();
+        assert_eq!(block_on(send(&mut tx, 2)), Err(Closed));
+        assert_eq!(rx.try_next(), Some(1));
+        assert_eq!(block_on(next(&mut rx)), None);
+    }
+
+    #[test]
+    fn feedback_loop() {
+        let prog = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26,
+            27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let phases = [9, 8, 7, 6, 5];
+        let mut vms: Vec<VM> = phases.iter().map(|_| VM::new(&prog)).collect();
+        let (mut txs, mut rxs): (Vec<_>, Vec<_>) = phases.iter().map(|_| channel(2)).unzip();
+        // amplifier i reads from channel i and writes to channel i + 1
+        txs.rotate_left(1);
+        for (vm, phase) in vms.iter_mut().zip(&phases) {
+            vm.push_input(*phase);
+        }
+        block_on(send(&mut txs[4], 0)).unwrap();
+
+        let runs = vms.iter_mut()
+            .zip(rxs.iter_mut().zip(txs.iter_mut()))
+            .map(|(vm, (rx, tx))| vm.run_async(rx, tx));
+        let results = block_on(join_all(runs));
+        assert!(results.iter().all(|r| *r == Ok(Status::Halted)));
+        // the last amplifier's final signal is left for the halted first
+        assert_eq!(rxs[0].try_next(), Some(139629729));
+    }
+
+    type Run<'a> = Pin<Box<dyn Future<Output = Result<Status, VmError>> + 'a>>;
+
+    #[test]
+    fn busy_vms_share_a_thread() {
+        // counts down from a large number, then outputs
+        let prog = assemble("
+            loop:   add [n], -1, [n]
+                    jnz [n], loop
+                    write 1
+                    halt
+            n:      data 50000
+        ").unwrap();
+        let (tx, mut rx) = channel(1);
+        let mut busy = VM::new(&prog);
+        let mut echo = VM::new(&[3, 0, 4, 0, 99]);
+        let mut out = Vec::new();
+        let runs: Vec<Run> = vec![
+            Box::pin(busy.run_async(iter(vec![]), tx)),
+            Box::pin(echo.run_async(&mut rx, &mut out)),
+        ];
+        assert_eq!(block_on(join_all(runs)), [Ok(Status::Halted), Ok(Status::Halted)]);
+        assert_eq!(out, [1]);
+    }
+
+    #[test]
+    fn output_closed() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        let mut vm = VM::new(&[104, 7, 99]);
+        let err = block_on(vm.run_async(iter(vec![]), tx)).unwrap_err();
+        assert_eq!(err, VmError::OutputClosed { pc: 0 });
+    }
+}
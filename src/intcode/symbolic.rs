@@ -0,0 +1,613 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use super::{Memory, Mode, Op, VmError};
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(isize),
+    // an unknown, by index into the declared variables
+    Var(usize),
+    Add(Sym, Sym),
+    Mul(Sym, Sym),
+    // 1 or 0, as stored by the less and equal instructions
+    Less(Sym, Sym),
+    Equal(Sym, Sym),
+    // a read through a symbolic address, resolved lazily against memory as
+    // it was when the read happened
+    Select(Sym, Rc<Memory<Sym>>),
+}
+
+pub type Sym = Rc<Expr>;
+
+impl Default for Expr {
+    fn default() -> Self {
+        Expr::Const(0)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(x) => write!(f, "{}", x),
+            Expr::Var(i) => write!(f, "x{}", i),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::Less(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Equal(a, b) => write!(f, "({} == {})", a, b),
+            Expr::Select(addr, _) => write!(f, "mem[{}]", addr),
+        }
+    }
+}
+
+// memory snapshots make derived output unreadable
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+fn constant(x: isize) -> Sym {
+    Rc::new(Expr::Const(x))
+}
+
+// the constructors fold constants, so concrete programs never build trees
+impl Expr {
+    pub fn value(&self) -> Option<isize> {
+        match self {
+            Expr::Const(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn add(a: &Sym, b: &Sym) -> Sym {
+        match (a.value(), b.value()) {
+            (Some(x), Some(y)) if x.checked_add(y).is_some() => constant(x + y),
+            (Some(0), _) => b.clone(),
+            (_, Some(0)) => a.clone(),
+            _ => Rc::new(Expr::Add(a.clone(), b.clone())),
+        }
+    }
+
+    pub fn mul(a: &Sym, b: &Sym) -> Sym {
+        match (a.value(), b.value()) {
+            (Some(x), Some(y)) if x.checked_mul(y).is_some() => constant(x * y),
+            (Some(0), _) | (_, Some(0)) => constant(0),
+            (Some(1), _) => b.clone(),
+            (_, Some(1)) => a.clone(),
+            _ => Rc::new(Expr::Mul(a.clone(), b.clone())),
+        }
+    }
+
+    pub fn less(a: &Sym, b: &Sym) -> Sym {
+        match (a.value(), b.value()) {
+            (Some(x), Some(y)) => constant((x < y) as isize),
+            _ => Rc::new(Expr::Less(a.clone(), b.clone())),
+        }
+    }
+
+    pub fn equal(a: &Sym, b: &Sym) -> Sym {
+        match (a.value(), b.value()) {
+            (Some(x), Some(y)) => constant((x == y) as isize),
+            _ if a == b => constant(1),
+            _ => Rc::new(Expr::Equal(a.clone(), b.clone())),
+        }
+    }
+
+    // None if evaluation overflows or reads a negative address
+    pub fn eval(&self, vars: &[isize]) -> Option<isize> {
+        match self {
+            Expr::Const(x) => Some(*x),
+            Expr::Var(i) => Some(vars[*i]),
+            Expr::Add(a, b) => a.eval(vars)?.checked_add(b.eval(vars)?),
+            Expr::Mul(a, b) => a.eval(vars)?.checked_mul(b.eval(vars)?),
+            Expr::Less(a, b) => Some((a.eval(vars)? < b.eval(vars)?) as isize),
+            Expr::Equal(a, b) => Some((a.eval(vars)? == b.eval(vars)?) as isize),
+            Expr::Select(addr, mem) => {
+                let addr = usize::try_from(addr.eval(vars)?).ok()?;
+                mem[addr].eval(vars)
+            },
+        }
+    }
+
+    // coefficients per variable and a constant, if the expression is linear
+    pub fn linear(&self) -> Option<(BTreeMap<usize, i128>, i128)> {
+        match self {
+            Expr::Const(x) => Some((BTreeMap::new(), *x as i128)),
+            Expr::Var(i) => Some(([(*i, 1)].iter().copied().collect(), 0)),
+            Expr::Add(a, b) => {
+                let (mut coeffs, c) = a.linear()?;
+                let (other, d) = b.linear()?;
+                for (i, k) in other {
+                    let sum = coeffs.get(&i).unwrap_or(&0).checked_add(k)?;
+                    coeffs.insert(i, sum);
+                }
+                Some((coeffs, c.checked_add(d)?))
+            },
+            Expr::Mul(a, b) => {
+                let ((coeffs, c), k) = match (a.value(), b.value()) {
+                    (Some(k), _) => (b.linear()?, k as i128),
+                    (_, Some(k)) => (a.linear()?, k as i128),
+                    _ => return None,
+                };
+                let coeffs = coeffs.into_iter()
+                    .map(|(i, x)| Some((i, x.checked_mul(k)?)))
+                    .collect::<Option<_>>()?;
+                Some((coeffs, c.checked_mul(k)?))
+            },
+            _ => None,
+        }
+    }
+}
+
+// a branch taken on the way: expr is non-zero exactly when holds is set
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub expr: Sym,
+    pub holds: bool,
+}
+
+impl Constraint {
+    pub fn eq(a: &Sym, value: isize) -> Self {
+        Self { expr: Expr::equal(a, &constant(value)), holds: true }
+    }
+
+    pub fn check(&self, vars: &[isize]) -> bool {
+        self.expr.eval(vars).is_some_and(|x| (x != 0) == self.holds)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum End {
+    Halted,
+    NeedsInput,
+    StepLimit,
+    // stopped at a branch because following both sides would exceed max_paths
+    PathLimit,
+    Error(VmError),
+    // something the executor can't follow, like a jump to a symbolic target
+    Unsupported { pc: usize, what: &'static str },
+}
+
+// one way through the program and what was true along it
+#[derive(Clone, Debug)]
+pub struct Path {
+    pub mem: Memory<Sym>,
+    pub pc: usize,
+    pub outputs: Vec<Sym>,
+    pub constraints: Vec<Constraint>,
+    pub end: End,
+}
+
+#[derive(Clone)]
+struct State {
+    mem: Memory<Sym>,
+    pc: usize,
+    base: isize,
+    input: VecDeque<Sym>,
+    outputs: Vec<Sym>,
+    constraints: Vec<Constraint>,
+    steps: usize,
+}
+
+impl State {
+    fn path(self, end: End) -> Path {
+        Path { mem: self.mem, pc: self.pc, outputs: self.outputs, constraints: self.constraints, end }
+    }
+
+    fn read(&self, ptr: Sym, mode: Mode) -> Result<Sym, End> {
+        let addr = match mode {
+            Mode::Imm => return Ok(ptr),
+            Mode::Ptr => ptr,
+            Mode::Rel => Expr::add(&constant(self.base), &ptr),
+        };
+        match addr.value() {
+            Some(a) => {
+                let a = usize::try_from(a)
+                    .map_err(|_| End::Error(VmError::NegativeAddress { pc: self.pc, addr: a }))?;
+                Ok(self.mem.get(a))
+            },
+            None => Ok(Rc::new(Expr::Select(addr, Rc::new(self.mem.clone())))),
+        }
+    }
+
+    fn write(&mut self, ptr: Sym, mode: Mode, value: Sym) -> Result<(), End> {
+        let pc = self.pc;
+        let addr = match mode {
+            Mode::Imm => return Err(End::Error(VmError::WriteToImmediate { pc })),
+            Mode::Ptr => ptr,
+            Mode::Rel => Expr::add(&constant(self.base), &ptr),
+        };
+        let a = addr.value().ok_or(End::Unsupported { pc, what: "write to a symbolic address" })?;
+        let a = usize::try_from(a).map_err(|_| End::Error(VmError::NegativeAddress { pc, addr: a }))?;
+        self.mem.set(a, value);
+        Ok(())
+    }
+
+    // executes one instruction, leaving the other side of a fork in `fork`
+    // even if this side then ends
+    fn step(&mut self, fork: &mut Option<State>) -> Result<(), End> {
+        let pc = self.pc;
+        let op_code = self.mem[pc].value().ok_or(End::Unsupported { pc, what: "symbolic op code" })?;
+        let op = Op::from(op_code).ok_or(End::Error(VmError::InvalidOpcode { pc, op_code }))?;
+        let p = |i: usize| self.mem.get(pc + 1 + i);
+        let (a, b, c) = (p(0), p(1), p(2));
+        match op {
+            Op::Add(am, bm, dm) | Op::Mul(am, bm, dm) | Op::Less(am, bm, dm) | Op::Equal(am, bm, dm) => {
+                let (x, y) = (self.read(a, am)?, self.read(b, bm)?);
+                let value = match op {
+                    Op::Add(_, _, _) => Expr::add(&x, &y),
+                    Op::Mul(_, _, _) => Expr::mul(&x, &y),
+                    Op::Less(_, _, _) => Expr::less(&x, &y),
+                    _ => Expr::equal(&x, &y),
+                };
+                self.write(c, dm, value)?;
+            },
+            Op::Read(m) => {
+                let value = self.input.pop_front().ok_or(End::NeedsInput)?;
+                self.write(a, m, value)?;
+            },
+            Op::Write(m) => {
+                let value = self.read(a, m)?;
+                self.outputs.push(value);
+            },
+            Op::Jump(m, am, dm) => {
+                let cond = self.read(a, am)?;
+                let dst = self.read(b, dm)?;
+                let target = || {
+                    let t = dst.value().ok_or(End::Unsupported { pc, what: "jump to a symbolic target" })?;
+                    usize::try_from(t).map_err(|_| End::Error(VmError::JumpOutOfRange { pc, target: t }))
+                };
+                match cond.value() {
+                    Some(x) if (x != 0) == m => self.pc = target()?,
+                    Some(_) => self.pc += 3,
+                    None => {
+                        let taken = target();
+                        let mut other = self.clone();
+                        other.constraints.push(Constraint { expr: cond.clone(), holds: !m });
+                        other.pc += 3;
+                        *fork = Some(other);
+                        self.constraints.push(Constraint { expr: cond, holds: m });
+                        self.pc = taken?;
+                    },
+                }
+                return Ok(());
+            },
+            Op::Rebase(m) => {
+                let offset = self.read(a, m)?.value()
+                    .ok_or(End::Unsupported { pc, what: "symbolic relative base" })?;
+                self.base = self.base.checked_add(offset)
+                    .ok_or(End::Error(VmError::Overflow { pc }))?;
+            },
+            Op::Ext(_, _) => return Err(End::Unsupported { pc, what: "extension op code" }),
+            Op::Halt => {
+                // like the VM, a halted path is left past the halt
+                self.pc += 1;
+                return Err(End::Halted);
+            },
+        }
+        self.pc += op.size();
+        Ok(())
+    }
+}
+
+// candidates tried before the solver gives up
+const MAX_CANDIDATES: u64 = 1 << 26;
+
+// runs a program with some cells and inputs unknown, following both sides
+// of every branch on an unknown, then solves for values of the unknowns
+pub struct Symbolic {
+    mem: Memory<Sym>,
+    input: VecDeque<Sym>,
+    domains: Vec<RangeInclusive<isize>>,
+    // forks past this many paths end with End::PathLimit
+    pub max_paths: usize,
+}
+
+impl Symbolic {
+    pub fn new(program: &[isize]) -> Self {
+        let image: Vec<Sym> = program.iter().map(|x| constant(*x)).collect();
+        Self {
+            mem: Memory::new(&image),
+            input: VecDeque::new(),
+            domains: Vec::new(),
+            max_paths: 1024,
+        }
+    }
+
+    fn var(&mut self, domain: RangeInclusive<isize>) -> Sym {
+        self.domains.push(domain);
+        Rc::new(Expr::Var(self.domains.len() - 1))
+    }
+
+    // makes mem[addr] unknown within domain; returns the variable's index
+    pub fn cell(&mut self, addr: usize, domain: RangeInclusive<isize>) -> usize {
+        let x = self.var(domain);
+        self.mem.set(addr, x);
+        self.domains.len() - 1
+    }
+
+    // queues an unknown input within domain; returns the variable's index
+    pub fn input(&mut self, domain: RangeInclusive<isize>) -> usize {
+        let x = self.var(domain);
+        self.input.push_back(x);
+        self.domains.len() - 1
+    }
+
+    pub fn push_input(&mut self, value: isize) {
+        self.input.push_back(constant(value));
+    }
+
+    // explores every path, each for at most max_steps instructions
+    pub fn run(&self, max_steps: usize) -> Vec<Path> {
+        let mut pending = vec![State {
+            mem: self.mem.clone(),
+            pc: 0,
+            base: 0,
+            input: self.input.clone(),
+            outputs: Vec::new(),
+            constraints: Vec::new(),
+            steps: 0,
+        }];
+        let mut paths = Vec::new();
+        while let Some(mut s) = pending.pop() {
+            let end = loop {
+                if s.steps >= max_steps {
+                    break End::StepLimit;
+                }
+                s.steps += 1;
+                let mut fork = None;
+                let result = s.step(&mut fork);
+                if let Some(fork) = fork {
+                    if paths.len() + pending.len() + 1 >= self.max_paths {
+                        break End::PathLimit;
+                    }
+                    pending.push(fork);
+                }
+                if let Err(end) = result {
+                    break end;
+                }
+            };
+            paths.push(s.path(end));
+        }
+        paths
+    }
+
+    // values for the variables, in declaration order, satisfying every
+    // constraint. A linear equation among them is solved for one variable
+    // while the rest are enumerated; otherwise everything is enumerated.
+    pub fn solve(&self, constraints: &[Constraint]) -> Option<Vec<isize>> {
+        let n = self.domains.len();
+        let check = |vars: &[isize]| constraints.iter().all(|c| c.check(vars));
+        let equation = constraints.iter()
+            .filter(|c| c.holds)
+            .filter_map(|c| match &*c.expr {
+                Expr::Equal(a, b) => {
+                    let (mut coeffs, c) = a.linear()?;
+                    let (other, d) = b.linear()?;
+                    for (i, k) in other {
+                        let diff = coeffs.get(&i).unwrap_or(&0).checked_sub(k)?;
+                        coeffs.insert(i, diff);
+                    }
+                    coeffs.retain(|_, k| *k != 0);
+                    Some((coeffs, c.checked_sub(d)?))
+                },
+                _ => None,
+            })
+            .find(|(coeffs, _)| !coeffs.is_empty());
+
+        let mut vars: Vec<isize> = self.domains.iter().map(|d| *d.start()).collect();
+        match equation {
+            // sum of coeffs * vars + c == 0
+            Some((coeffs, c)) => {
+                let (k, a) = coeffs.iter().max_by_key(|(_, a)| a.abs()).map(|(k, a)| (*k, *a)).unwrap();
+                let others: Vec<usize> = (0..n).filter(|i| *i != k).collect();
+                self.enumerate(&others, &mut vars, |vars| {
+                    let rest = coeffs.iter()
+                        .filter(|(i, _)| **i != k)
+                        .try_fold(c, |acc, (i, x)| acc.checked_add(x.checked_mul(vars[*i] as i128)?));
+                    let rest = match rest {
+                        Some(rest) if rest % a == 0 => rest,
+                        _ => return false,
+                    };
+                    match isize::try_from(-rest / a) {
+                        Ok(x) if self.domains[k].contains(&x) => {
+                            vars[k] = x;
+                            check(vars)
+                        },
+                        _ => false,
+                    }
+                })
+            },
+            None => self.enumerate(&(0..n).collect::<Vec<_>>(), &mut vars, |vars| check(vars)),
+        }
+    }
+
+    // tries every combination of values for the given variables
+    fn enumerate<F: FnMut(&mut Vec<isize>) -> bool>(&self, which: &[usize], vars: &mut Vec<isize>, mut found: F) -> Option<Vec<isize>> {
+        for i in which {
+            vars[*i] = *self.domains[*i].start();
+        }
+        let mut tried = 0;
+        loop {
+            if found(vars) {
+                return Some(vars.clone());
+            }
+            tried += 1;
+            if tried >= MAX_CANDIDATES {
+                return None;
+            }
+            // odometer increment, first variable fastest
+            let mut carried = true;
+            for i in which {
+                if vars[*i] < *self.domains[*i].end() {
+                    vars[*i] += 1;
+                    carried = false;
+                    break;
+                }
+                vars[*i] = *self.domains[*i].start();
+            }
+            if carried {
+                return None;
+            }
+        }
+    }
+
+    // values for the variables that leave mem[addr] == value when the
+    // program halts, on any path
+    pub fn solve_mem(&self, addr: usize, value: isize, max_steps: usize) -> Option<Vec<isize>> {
+        self.run(max_steps).into_iter()
+            .filter(|p| p.end == End::Halted)
+            .find_map(|p| {
+                let mut constraints = p.constraints.clone();
+                constraints.push(Constraint::eq(&p.mem[addr], value));
+                self.solve(&constraints)
+            })
+    }
+
+    // values for the variables that make the program output value, on any
+    // path that halts
+    pub fn solve_output(&self, value: isize, max_steps: usize) -> Option<Vec<isize>> {
+        self.run(max_steps).into_iter()
+            .filter(|p| p.end == End::Halted)
+            .flat_map(|p| p.outputs.iter().map(|o| (p.constraints.clone(), o.clone())).collect::<Vec<_>>())
+            .find_map(|(mut constraints, o)| {
+                constraints.push(Constraint::eq(&o, value));
+                self.solve(&constraints)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    #[test]
+    fn day02b() {
+        let prog = crate::intcode::parse(&crate::load("02.txt")).unwrap();
+        let mut s = Symbolic::new(&prog);
+        let noun = s.cell(1, 0..=99);
+        let verb = s.cell(2, 0..=99);
+
+        let paths = s.run(10_000);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].end, End::Halted);
+        // the result is linear in noun and verb
+        let (coeffs, _) = paths[0].mem[0].linear().unwrap();
+        assert_eq!(coeffs.keys().copied().collect::<Vec<_>>(), vec![noun, verb]);
+
+        let x = s.solve_mem(0, 19690720, 10_000).unwrap();
+        assert_eq!(100 * x[noun] + x[verb], 5485);
+
+        let x = s.solve_mem(0, 4570637, 10_000).unwrap();
+        assert_eq!((x[noun], x[verb]), (12, 2));
+    }
+
+    #[test]
+    fn forks() {
+        let prog = assemble("
+                    read [x]
+                    equal [x], 7, [t]
+                    jz [t], no
+                    write 1
+                    halt
+            no:     write 0
+                    halt
+            x:      data 0
+            t:      data 0
+        ").unwrap();
+        let mut s = Symbolic::new(&prog);
+        s.input(0..=100);
+        let paths = s.run(100);
+        assert_eq!(paths.len(), 2);
+        assert!(paths.iter().all(|p| p.end == End::Halted && p.constraints.len() == 1));
+        assert_eq!(s.solve_output(1, 100), Some(vec![7]));
+        assert_eq!(s.solve_output(0, 100), Some(vec![0]));
+        assert_eq!(s.solve_output(2, 100), None);
+    }
+
+    #[test]
+    fn fork_bookkeeping() {
+        let prog = assemble("
+                    read [x]
+                    equal [x], 7, [t]
+                    jnz [t], -1
+                    write 0
+                    halt
+            x:      data 0
+            t:      data 0
+        ").unwrap();
+        let mut s = Symbolic::new(&prog);
+        s.input(0..=100);
+        // the fall through takes exactly five steps and still runs when the
+        // other side can't jump
+        let paths = s.run(5);
+        assert_eq!(paths.len(), 2);
+        let ends: Vec<End> = paths.iter().map(|p| p.end).collect();
+        assert!(ends.contains(&End::Error(VmError::JumpOutOfRange { pc: 6, target: -1 })), "{:?}", ends);
+        assert!(ends.contains(&End::Halted), "{:?}", ends);
+    }
+
+    #[test]
+    fn nonlinear() {
+        // x * x + y == 58 with y odd and below 10
+        let prog = assemble("
+                    read [x]
+                    read [y]
+                    mul [x], [x], [t]
+                    add [t], [y], [t]
+                    write [t]
+                    halt
+            x:      data 0
+            y:      data 0
+            t:      data 0
+        ").unwrap();
+        let mut s = Symbolic::new(&prog);
+        s.input(0..=20);
+        s.input(0..=9);
+        let paths = s.run(100);
+        assert_eq!(paths[0].outputs[0].to_string(), "((x0 * x0) + x1)");
+        assert_eq!(paths[0].outputs[0].linear(), None);
+        assert_eq!(s.solve_output(58, 100), Some(vec![7, 9]));
+    }
+
+    #[test]
+    fn unsupported() {
+        // a loop counting down an unknown forks on every test
+        let prog = assemble("
+                    read [n]
+            loop:   add [n], -1, [n]
+                    jnz [n], loop
+                    halt
+            n:      data 0
+        ").unwrap();
+        let mut s = Symbolic::new(&prog);
+        s.input(1..=1000);
+        s.max_paths = 10;
+        let paths = s.run(1000);
+        assert_eq!(paths.len(), 10);
+        assert!(paths.iter().any(|p| p.end == End::PathLimit));
+        assert_eq!(s.solve_output(0, 1000), None);
+
+        let mut s = Symbolic::new(&[3, 5, 105, 1, 5, 0]);
+        s.input(0..=9);
+        let paths = s.run(10);
+        // jnz 1, [5] reads the unknown as its target
+        assert_eq!(paths[0].end, End::Unsupported { pc: 2, what: "jump to a symbolic target" });
+    }
+
+    #[test]
+    fn halt_pc() {
+        // the VM's pc ends up past a halt, and so does a path's
+        let mut vm = crate::intcode::VM::new(&[9, 0, 99]);
+        vm.run().unwrap();
+        let paths = Symbolic::new(&[9, 0, 99]).run(10);
+        assert_eq!(paths[0].end, End::Halted);
+        assert_eq!(paths[0].pc, vm.pc);
+        assert_eq!(paths[0].pc, 3);
+    }
+}
@@ -0,0 +1,31 @@
+// fixtures shared by the test modules
+
+use super::asm::{assemble, assemble_with};
+use super::ext::{Param, Spec};
+use super::VM;
+
+// reads n, writes n down to 1, then halts
+pub fn countdown() -> Vec<isize> {
+    assemble("
+                read [n]
+        loop:   write [n]
+                add [n], -1, [n]
+                jnz [n], loop
+                halt
+        n:      data 0
+    ").unwrap()
+}
+
+// neg x, [y] stores -x in y
+pub const NEG: Spec = Spec { code: 10, mnemonic: "neg", params: &[Param::In, Param::Out] };
+
+// a VM for source that may use neg, with neg registered
+pub fn with_neg(source: &str) -> VM {
+    let mut vm = VM::new(&assemble_with(source, &[NEG]).unwrap());
+    vm.register(NEG, |c| {
+        let v = -*c.arg(0);
+        c.set(1, v);
+        Ok(())
+    }).unwrap();
+    vm
+}
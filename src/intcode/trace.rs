@@ -0,0 +1,406 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::ext::{self, Spec};
+use super::{Op, Status, VM, VmError};
+use super::word::Word;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event<W = isize> {
+    pub pc: usize,
+    pub op: Op,
+    // relative base before the instruction ran
+    pub base: isize,
+    // values of the parameters read, in order
+    pub operands: Vec<W>,
+    // (addr, old, new)
+    pub writes: Vec<(usize, W, W)>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+}
+
+impl<W: fmt::Display> fmt::Display for Event<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04} {}", self.pc, self.op.mnemonic())?;
+        for x in &self.operands {
+            write!(f, " {}", x)?;
+        }
+        for (addr, old, new) in &self.writes {
+            write!(f, " [{}] {} -> {}", addr, old, new)?;
+        }
+        if let Some(x) = &self.input {
+            write!(f, " in {}", x)?;
+        }
+        if let Some(x) = &self.output {
+            write!(f, " out {}", x)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace<W = isize> {
+    pub events: Vec<Event<W>>,
+    // the instruction currently executing
+    current: Option<Event<W>>,
+}
+
+impl<W: Word> Trace<W> {
+    pub fn inputs(&self) -> Vec<W> {
+        self.events.iter().filter_map(|e| e.input.clone()).collect()
+    }
+
+    pub fn outputs(&self) -> Vec<W> {
+        self.events.iter().filter_map(|e| e.output.clone()).collect()
+    }
+
+    pub(super) fn begin(&mut self, pc: usize, op: Op, base: isize) {
+        self.current = Some(Event {
+            pc,
+            op,
+            base,
+            operands: Vec::new(),
+            writes: Vec::new(),
+            input: None,
+            output: None,
+        });
+    }
+
+    pub(super) fn read(&mut self, value: &W) {
+        if let Some(e) = &mut self.current {
+            e.operands.push(value.clone());
+        }
+    }
+
+    pub(super) fn write(&mut self, addr: usize, old: &W, new: &W) {
+        if let Some(e) = &mut self.current {
+            e.writes.push((addr, old.clone(), new.clone()));
+        }
+    }
+
+    pub(super) fn input(&mut self, value: &W) {
+        if let Some(e) = &mut self.current {
+            e.input = Some(value.clone());
+        }
+    }
+
+    pub(super) fn output(&mut self, value: &W) {
+        if let Some(e) = &mut self.current {
+            e.output = Some(value.clone());
+        }
+    }
+
+    pub(super) fn finish(&mut self) {
+        if let Some(e) = self.current.take() {
+            self.events.push(e);
+        }
+    }
+
+    pub(super) fn discard(&mut self) {
+        self.current = None;
+    }
+}
+
+// the file format only holds isize words
+impl Trace {
+    // "ICT1" then one record per event, every number a LEB128 varint with
+    // signed values zigzag encoded
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = b"ICT1".to_vec();
+        put_uvar(&mut buf, self.events.len() as u64);
+        for e in &self.events {
+            put_uvar(&mut buf, e.pc as u64);
+            put_var(&mut buf, e.op.encode());
+            put_var(&mut buf, e.base);
+            put_uvar(&mut buf, e.operands.len() as u64);
+            for x in &e.operands {
+                put_var(&mut buf, *x);
+            }
+            put_uvar(&mut buf, e.writes.len() as u64);
+            for (addr, old, new) in &e.writes {
+                put_uvar(&mut buf, *addr as u64);
+                put_var(&mut buf, *old);
+                put_var(&mut buf, *new);
+            }
+            let flags = e.input.map_or(0, |_| 1) | e.output.map_or(0, |_| 2);
+            buf.push(flags);
+            for x in e.input.iter().chain(e.output.iter()) {
+                put_var(&mut buf, *x);
+            }
+        }
+        w.write_all(&buf)
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        Self::read_from_with(r, &[])
+    }
+
+    // for traces of a VM with extensions, e.g. from vm.extensions().specs()
+    pub fn read_from_with<R: Read>(r: &mut R, specs: &[Spec]) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        if !bytes.starts_with(b"ICT1") {
+            return Err(invalid("not an intcode trace"));
+        }
+        let mut r = Reader { bytes: &bytes, pos: 4 };
+
+        let mut events = Vec::new();
+        for _ in 0..r.uvar()? {
+            let pc = r.uvar()? as usize;
+            let op = ext::decode(r.var()?, specs).ok_or_else(|| invalid("bad op code"))?;
+            let base = r.var()?;
+            let operands = (0..r.uvar()?).map(|_| r.var()).collect::<io::Result<Vec<isize>>>()?;
+            let mut writes = Vec::new();
+            for _ in 0..r.uvar()? {
+                writes.push((r.uvar()? as usize, r.var()?, r.var()?));
+            }
+            let flags = r.byte()?;
+            let input = if flags & 1 != 0 { Some(r.var()?) } else { None };
+            let output = if flags & 2 != 0 { Some(r.var()?) } else { None };
+            events.push(Event { pc, op, base, operands, writes, input, output });
+        }
+        Ok(Self { events, current: None })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn put_uvar(buf: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        buf.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    buf.push(x as u8);
+}
+
+fn put_var(buf: &mut Vec<u8>, x: isize) {
+    let x = x as i64;
+    put_uvar(buf, ((x << 1) ^ (x >> 63)) as u64);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let b = *self.bytes.get(self.pos).ok_or_else(|| invalid("truncated trace"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn uvar(&mut self) -> io::Result<u64> {
+        let mut x = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            x |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+
+    fn var(&mut self) -> io::Result<isize> {
+        let x = self.uvar()?;
+        Ok(((x >> 1) as i64 ^ -((x & 1) as i64)) as isize)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayError<W = isize> {
+    // the run went somewhere the recording didn't
+    Diverged { step: usize, expected: Box<Event<W>>, actual: Box<Event<W>> },
+    // the recording continues past where the run stopped
+    Stopped { step: usize, status: Status<W> },
+    Failed { step: usize, error: VmError },
+}
+
+// runs the program again with the recorded inputs and checks that every step
+// matches the trace; returns the VM where the recording ends
+pub fn replay<W: Word>(program: &[W], trace: &Trace<W>) -> Result<VM<W>, ReplayError<W>> {
+    let mut vm = VM::from_words(program);
+    for x in trace.inputs() {
+        vm.push_input(x);
+    }
+    vm.record();
+
+    for (step, expected) in trace.events.iter().enumerate() {
+        match vm.step() {
+            Ok(Status::Running) | Ok(Status::Output(_)) | Ok(Status::Watch(_)) | Ok(Status::Halted) => (),
+            Ok(status) => return Err(ReplayError::Stopped { step, status }),
+            Err(error) => return Err(ReplayError::Failed { step, error }),
+        }
+        let actual = vm.trace().and_then(|t| t.events.last()).cloned();
+        match actual {
+            Some(actual) if actual == *expected => (),
+            Some(actual) => {
+                return Err(ReplayError::Diverged { step, expected: Box::new(expected.clone()), actual: Box::new(actual) });
+            },
+            None => return Err(ReplayError::Stopped { step, status: Status::Halted }),
+        }
+    }
+    Ok(vm)
+}
+
+impl<W: Word> VM<W> {
+    // starts recording every instruction executed from here on
+    pub fn record(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    pub fn trace(&self) -> Option<&Trace<W>> {
+        self.trace.as_ref()
+    }
+
+    pub fn take_trace(&mut self) -> Option<Trace<W>> {
+        self.trace.take()
+    }
+
+    // undoes the last recorded instruction, putting back any input it
+    // consumed so stepping forward again repeats it
+    pub fn step_back(&mut self) -> Option<Event<W>> {
+        let event = self.trace.as_mut()?.events.pop()?;
+        for (addr, old, _) in event.writes.iter().rev() {
+            self.mem[*addr] = old.clone();
+        }
+        if let Some(x) = &event.input {
+            self.input.push_front(x.clone());
+        }
+        self.pc = event.pc;
+        self.base = event.base;
+        self.halt = false;
+        self.reset_loops();
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::testing::countdown;
+
+    fn run(vm: &mut VM) -> Vec<isize> {
+        let mut out = Vec::new();
+        while let Ok(status) = vm.run() {
+            match status {
+                Status::Output(x) => out.push(x),
+                _ => break,
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn extensions() {
+        let mut vm = crate::intcode::testing::with_neg("neg 5, [x]\nhalt\nx: data 0");
+        vm.record();
+        run(&mut vm);
+        let trace = vm.take_trace().unwrap();
+        assert_eq!(trace.events[0].to_string(), "0000 neg 5 [4] 0 -> -5");
+
+        let mut file = Vec::new();
+        trace.write_to(&mut file).unwrap();
+        assert!(Trace::read_from(&mut &file[..]).is_err());
+        assert_eq!(Trace::read_from_with(&mut &file[..], &vm.extensions().specs()).unwrap(), trace);
+    }
+
+    #[test]
+    fn records() {
+        let mut vm = VM::new(&countdown());
+        vm.push_input(2);
+        vm.record();
+        assert_eq!(run(&mut vm), vec![2, 1]);
+
+        let trace = vm.take_trace().unwrap();
+        let lines: Vec<String> = trace.events.iter().map(|e| e.to_string()).collect();
+        assert_eq!(lines, vec![
+            "0000 read [12] 0 -> 2 in 2",
+            "0002 write 2 out 2",
+            "0004 add 2 -1 [12] 2 -> 1",
+            "0008 jnz 1 2",
+            "0002 write 1 out 1",
+            "0004 add 1 -1 [12] 1 -> 0",
+            "0008 jnz 0 2",
+            "0011 halt",
+        ]);
+        assert_eq!(trace.inputs(), vec![2]);
+        assert_eq!(trace.outputs(), vec![2, 1]);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let mut vm = VM::new(&countdown());
+        vm.push_input(-300);
+        vm.record();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.base = 1 << 40;
+        vm.step().unwrap();
+        let trace = vm.take_trace().unwrap();
+
+        let mut file = Vec::new();
+        trace.write_to(&mut file).unwrap();
+        assert_eq!(&file[..4], b"ICT1");
+        assert_eq!(Trace::read_from(&mut &file[..]).unwrap(), trace);
+
+        assert!(Trace::read_from(&mut &file[..file.len() - 1]).is_err());
+        assert!(Trace::read_from(&mut &b"nope"[..]).is_err());
+    }
+
+    #[test]
+    fn replays_threaded_run() {
+        use std::thread;
+
+        let (mut vm, input, output) = VM::with_io(&countdown());
+        vm.record();
+        let i = thread::spawn(move || input.send(3).unwrap());
+        let o = thread::spawn(move || output.iter().count());
+        let vm = thread::spawn(move || {
+            vm.run().unwrap();
+            vm.take_trace().unwrap()
+        });
+        i.join().expect("input thread panicked");
+        let trace = vm.join().expect("vm thread panicked");
+        assert_eq!(o.join().expect("output thread panicked"), 3);
+
+        let vm = replay(&countdown(), &trace).unwrap();
+        assert!(vm.halt);
+
+        let mut patched = countdown();
+        patched[6] = 2;
+        match replay(&patched, &trace) {
+            Err(ReplayError::Diverged { step: 2, actual, .. }) => {
+                assert_eq!(actual.to_string(), "0004 add 3 2 [12] 3 -> 5");
+            },
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn steps_back() {
+        let mut vm = VM::new(&countdown());
+        vm.push_input(2);
+        vm.record();
+        assert_eq!(run(&mut vm), vec![2, 1]);
+        assert!(vm.halt);
+
+        assert_eq!(vm.step_back().unwrap().to_string(), "0011 halt");
+        assert!(!vm.halt);
+        vm.step_back();
+        vm.step_back();
+        assert_eq!(vm.pc, 4);
+        assert_eq!(vm.mem[12], 1);
+
+        while vm.step_back().is_some() {}
+        assert_eq!(vm.pc, 0);
+        assert_eq!(vm.mem[12], 0);
+
+        // the input is put back, so the run repeats exactly
+        assert_eq!(run(&mut vm), vec![2, 1]);
+        assert_eq!(vm.trace().unwrap().events.len(), 8);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::Op;
+use super::word::Word;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    // either of the above
+    Any,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Hit<W = isize> {
+    pub pc: usize,
+    pub op: Op,
+    pub addr: usize,
+    // Read or Write
+    pub access: Access,
+    pub old: W,
+    // same as old for reads
+    pub new: W,
+}
+
+impl<W: fmt::Display> fmt::Display for Hit<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Write => write!(f, "{:04} {} wrote [{}] {} -> {}", self.pc, self.op.mnemonic(), self.addr, self.old, self.new),
+            _ => write!(f, "{:04} {} read [{}] = {}", self.pc, self.op.mnemonic(), self.addr, self.old),
+        }
+    }
+}
+
+type Callback<W> = Box<dyn FnMut(&Hit<W>) + Send>;
+
+// accesses are recorded while an instruction executes and turned into hits
+// once it completes, so a hit can name the whole instruction
+#[derive(Default)]
+pub struct Watches<W = isize> {
+    points: Vec<(RangeInclusive<usize>, Access)>,
+    callback: Option<Callback<W>>,
+    accesses: Vec<(usize, Access, W, W)>,
+    pending: VecDeque<Hit<W>>,
+}
+
+impl<W: fmt::Debug> fmt::Debug for Watches<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watches")
+            .field("points", &self.points)
+            .field("callback", &self.callback.is_some())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl<W: Word> Watches<W> {
+    pub fn add(&mut self, range: RangeInclusive<usize>, access: Access) {
+        self.points.push((range, access));
+    }
+
+    // removes every watchpoint covering addr
+    pub fn remove(&mut self, addr: usize) -> bool {
+        let before = self.points.len();
+        self.points.retain(|(range, _)| !range.contains(&addr));
+        self.points.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn set_callback(&mut self, callback: Option<Callback<W>>) {
+        self.callback = callback;
+    }
+
+    #[inline(always)]
+    pub fn record(&mut self, addr: usize, access: Access, old: &W, new: &W) {
+        let watched = self.points.iter().any(|(range, a)| {
+            range.contains(&addr) && (*a == Access::Any || *a == access)
+        });
+        if watched {
+            self.accesses.push((addr, access, old.clone(), new.clone()));
+        }
+    }
+
+    // completes the instruction at pc; hits go to the callback if there is
+    // one and are queued for the VM to stop on otherwise
+    pub fn finish(&mut self, pc: usize, op: Op) {
+        for (addr, access, old, new) in self.accesses.drain(..) {
+            let hit = Hit { pc, op, addr, access, old, new };
+            match &mut self.callback {
+                Some(f) => f(&hit),
+                None => self.pending.push_back(hit),
+            }
+        }
+    }
+
+    // the instruction didn't complete, so nothing it touched counts
+    pub fn discard(&mut self) {
+        self.accesses.clear();
+    }
+
+    pub fn next_hit(&mut self) -> Option<Hit<W>> {
+        self.pending.pop_front()
+    }
+
+    pub fn push_front(&mut self, hit: Hit<W>) {
+        self.pending.push_front(hit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::testing;
+    use crate::intcode::{Mode, Status, VM};
+    use std::sync::{Arc, Mutex};
+
+    fn countdown() -> VM {
+        VM::new(&testing::countdown())
+    }
+
+    #[test]
+    fn stops_on_write() {
+        let mut vm = countdown();
+        vm.watch(12..=12, Access::Write);
+        vm.push_input(2);
+
+        let read = Op::Read(Mode::Ptr);
+        let add = Op::Add(Mode::Ptr, Mode::Imm, Mode::Ptr);
+        let hit = |pc, op, old, new| Status::Watch(Hit { pc, op, addr: 12, access: Access::Write, old, new });
+        assert_eq!(vm.run(), Ok(hit(0, read, 0, 2)));
+        assert_eq!(vm.run(), Ok(Status::Output(2)));
+        assert_eq!(vm.run(), Ok(hit(4, add, 2, 1)));
+        assert_eq!(vm.run(), Ok(Status::Output(1)));
+        assert_eq!(vm.run(), Ok(hit(4, add, 1, 0)));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn reads_and_output() {
+        // the output is reported first and the read right after it
+        let mut vm = countdown();
+        vm.watch(10..=20, Access::Read);
+        vm.push_input(1);
+        assert_eq!(vm.run(), Ok(Status::Output(1)));
+        match vm.run() {
+            Ok(Status::Watch(hit)) => assert_eq!(hit.to_string(), "0002 write read [12] = 1"),
+            status => panic!("unexpected {:?}", status),
+        }
+        assert!(vm.unwatch(12));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn callback() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = log.clone();
+        let mut vm = countdown();
+        vm.watch(12..=12, Access::Any);
+        vm.on_watch(move |hit| sink.lock().unwrap().push(hit.to_string()));
+        vm.push_input(1);
+        assert_eq!(vm.run(), Ok(Status::Output(1)));
+        assert_eq!(vm.run(), Ok(Status::Halted));
+        assert_eq!(*log.lock().unwrap(), vec![
+            "0000 read wrote [12] 0 -> 1",
+            "0002 write read [12] = 1",
+            "0004 add read [12] = 1",
+            "0004 add wrote [12] 1 -> 0",
+            "0008 jnz read [12] = 0",
+        ]);
+    }
+}
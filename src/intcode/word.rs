@@ -0,0 +1,87 @@
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+// what happens when add or mul doesn't fit in the word type
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    // fail with VmError::Overflow
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+// a memory cell; addresses, jump targets and op codes are converted through
+// isize, so only arithmetic and comparisons use the full width
+pub trait Word: Clone + Default + Eq + Ord + Hash + fmt::Debug + fmt::Display + FromStr + Send + 'static {
+    fn from_isize(x: isize) -> Self;
+
+    // None if the value doesn't fit
+    fn to_isize(&self) -> Option<isize>;
+
+    // None on overflow, which only Checked can produce
+    fn add(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    fn mul(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+macro_rules! primitive_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            fn from_isize(x: isize) -> Self {
+                x as $t
+            }
+
+            fn to_isize(&self) -> Option<isize> {
+                use std::convert::TryFrom;
+                isize::try_from(*self).ok()
+            }
+
+            fn add(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                match overflow {
+                    Overflow::Checked => self.checked_add(*other),
+                    Overflow::Wrapping => Some(self.wrapping_add(*other)),
+                    Overflow::Saturating => Some(self.saturating_add(*other)),
+                }
+            }
+
+            fn mul(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                match overflow {
+                    Overflow::Checked => self.checked_mul(*other),
+                    Overflow::Wrapping => Some(self.wrapping_mul(*other)),
+                    Overflow::Saturating => Some(self.saturating_mul(*other)),
+                }
+            }
+        }
+    )*};
+}
+
+// no i32: isize programs may rely on addresses and values above 2^31
+primitive_word!(isize, i64, i128);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let max = i64::MAX;
+        assert_eq!(max.add(&1, Overflow::Checked), None);
+        assert_eq!(max.add(&1, Overflow::Wrapping), Some(i64::MIN));
+        assert_eq!(max.add(&1, Overflow::Saturating), Some(i64::MAX));
+        assert_eq!(max.mul(&-2, Overflow::Saturating), Some(i64::MIN));
+        assert_eq!(3i64.mul(&4, Overflow::Checked), Some(12));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(i128::from_isize(-5), -5);
+        assert_eq!((-5i128).to_isize(), Some(-5));
+        assert_eq!((1i128 << 100).to_isize(), None);
+        assert!(0i128.is_zero());
+    }
+}
@@ -1,12 +1,12 @@
 mod fuel;
-mod intcode;
+pub mod intcode;
 mod password;
 mod tree;
 mod wires;
 
 use std::io::prelude::Read;
 
-fn load(input_file: &str) -> String {
+pub fn load(input_file: &str) -> String {
     let filename = std::path::Path::new("./input").join(input_file);
     let mut file = std::fs::File::open(filename).unwrap();
     let mut contents = String::new();
@@ -50,7 +50,7 @@ mod tests {
         program[1] = 12;
         program[2] = 2;
         let mut vm = intcode::VM::new(&program);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.mem[0], 4570637);
     }
 
@@ -67,7 +67,7 @@ mod tests {
                 program[2] = verb;
 
                 let mut vm = intcode::VM::new(&program);
-                vm.run();
+                vm.run().unwrap();
                 if vm.mem[0] == 19690720 {
                     let result = 100*noun + verb;
                     assert_eq!(result, 5485);
@@ -155,7 +155,7 @@ mod tests {
         let vm = thread::spawn(move || vm.run());
         i.join().expect("input thread panicked");
         let result = o.join().expect("output thread panicked");
-        vm.join().expect("vm thread panicked");
+        vm.join().expect("vm thread panicked").unwrap();
         assert_eq!(result, 13346482);
     }
 
@@ -182,7 +182,7 @@ mod tests {
         let vm = thread::spawn(move || vm.run());
         i.join().expect("input thread panicked");
         let result = o.join().expect("output thread panicked");
-        vm.join().expect("vm thread panicked");
+        vm.join().expect("vm thread panicked").unwrap();
         assert_eq!(result, 12111395);
     }
 